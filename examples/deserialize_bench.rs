@@ -0,0 +1,53 @@
+//! Measures `LogEntry::deserialize` over a stream of short entries
+//!
+//! Compares the default timestamp format, which has a hand-rolled parser, with an equivalent format
+//! which goes through `chrono`'s parser. Criterion isn't available to the build, so the loops are
+//! timed with `Instant` and the best of a few rounds is reported. Run it in a release build:
+//!
+//! ```sh
+//! cargo run --release --example deserialize_bench
+//! ```
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+use svmgr::log::{LogEntry, TimestampFormat};
+
+const ENTRIES: u32 = 100_000;
+const ROUNDS: usize = 5;
+
+fn frames(format: &TimestampFormat) -> Vec<Vec<u8>> {
+    (0..ENTRIES)
+        .map(|i| {
+            let payload = format!("line {i}\n");
+            let mut frame = Vec::new();
+            LogEntry::new(payload.as_bytes()).serialize(format, &mut frame);
+            frame
+        })
+        .collect()
+}
+
+/// best time per entry over all rounds
+fn per_entry(format: &TimestampFormat) -> Duration {
+    let frames = frames(format);
+    (0..ROUNDS)
+        .map(|_| {
+            let start = Instant::now();
+            for frame in &frames {
+                black_box(LogEntry::deserialize(black_box(frame), format).unwrap());
+            }
+            start.elapsed() / ENTRIES
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    let fast = TimestampFormat::default();
+    // formats the same timestamps but isn't recognized as the default layout
+    let chrono = TimestampFormat::new("%Y-%m-%d %H:%M:%S%.6f").unwrap();
+    let fast = per_entry(&fast);
+    let chrono = per_entry(&chrono);
+    println!("default format, hand-rolled parser: {fast:?} per entry");
+    println!("equivalent format, chrono parser:   {chrono:?} per entry");
+    println!("speedup: {:.1}x", chrono.as_secs_f64() / fast.as_secs_f64());
+}
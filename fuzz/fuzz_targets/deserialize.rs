@@ -0,0 +1,38 @@
+//! Arbitrary frames for `LogEntry::deserialize` and `TimestampFormat::deserialize_header`
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use svmgr::log::{LogEntry, TimestampFormat};
+
+const FORMATS: &[&str] = &[
+    "%Y-%m-%d %H:%M:%S.%6f",
+    "%Y%m%dT%H%M%S%.3f",
+    "%b %d %H:%M:%S%.9f %Y",
+];
+
+fuzz_target!(|data: &[u8]| {
+    // the first byte picks the timestamp format, the rest is the frame
+    let Some((&selector, frame)) = data.split_first() else {
+        return;
+    };
+    let format = TimestampFormat::new(FORMATS[usize::from(selector) % FORMATS.len()]).unwrap();
+    if let Ok(entry) = LogEntry::deserialize(frame, &format) {
+        assert_round_trip(&entry, &format);
+    }
+    if let Ok(Some(format)) = TimestampFormat::deserialize_header(frame) {
+        let mut header = Vec::new();
+        format.serialize_header(&mut header);
+        let again = TimestampFormat::deserialize_header(&header).unwrap();
+        assert_eq!(again, Some(format));
+    }
+});
+
+/// a deserialized entry serializes into a frame which deserializes to the same entry
+fn assert_round_trip(entry: &LogEntry, format: &TimestampFormat) {
+    let mut frame = Vec::new();
+    entry.serialize(format, &mut frame);
+    let again = LogEntry::deserialize(&frame, format).unwrap();
+    assert_eq!(again.timestamp(), entry.timestamp());
+    assert_eq!(again.as_slice(), entry.as_slice());
+}
@@ -0,0 +1,23 @@
+//! Arbitrary streams for `LogReader`, including header frames switching the timestamp format
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use std::io::Cursor;
+use svmgr::log::{self, LogEntry};
+
+fuzz_target!(|data: &[u8]| {
+    let mut entries = log::iter_entries(Cursor::new(data));
+    while let Some(entry) = entries.next() {
+        let Ok(entry) = entry else {
+            continue;
+        };
+        // a deserialized entry serializes into a frame which deserializes to the same entry
+        let format = entries.log_reader().format();
+        let mut frame = Vec::new();
+        entry.serialize(format, &mut frame);
+        let again = LogEntry::deserialize(&frame, format).unwrap();
+        assert_eq!(again.timestamp(), entry.timestamp());
+        assert_eq!(again.as_slice(), entry.as_slice());
+    }
+});
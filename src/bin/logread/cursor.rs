@@ -0,0 +1,179 @@
+//! Read positions saved with `--from-cursor`
+//!
+//! The cursor file has one line per log: `{tag} {inode} {offset}`, where `offset` is the position
+//! right after the last entry that was written to stdout. The inode tells whether `current` is
+//! still the file the offset belongs to or whether the log was rotated in the meantime.
+
+use crate::Tag;
+use anyhow::{bail, Context, Result};
+use camino::{Utf8Path as Path, Utf8PathBuf};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::io::ErrorKind;
+
+/// Position in a log file right after an entry
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cursor {
+    pub inode: u64,
+    pub offset: u64,
+}
+
+pub struct Cursors {
+    path: Utf8PathBuf,
+    positions: BTreeMap<String, Cursor>,
+    /// positions changed since the last save
+    dirty: bool,
+}
+
+impl Cursors {
+    /// loads the cursor file, a missing file means nothing was read yet
+    pub fn load(path: &Path) -> Result<Cursors> {
+        let mut positions = BTreeMap::new();
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err).with_context(|| format!("read cursor file `{path}`")),
+        };
+        for (number, line) in contents.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let cursor = parse_line(line)
+                .with_context(|| format!("invalid cursor file `{path}` line {}", number + 1))?;
+            positions.insert(cursor.0.to_owned(), cursor.1);
+        }
+        Ok(Cursors {
+            path: path.to_owned(),
+            positions,
+            dirty: false,
+        })
+    }
+
+    pub fn get(&self, tag: Tag) -> Option<Cursor> {
+        self.positions.get(&tag.to_string()).copied()
+    }
+
+    pub fn update(&mut self, tag: Tag, cursor: Cursor) {
+        self.positions.insert(tag.to_string(), cursor);
+        self.dirty = true;
+    }
+
+    /// writes the positions if they changed, the file is replaced atomically so a crash leaves
+    /// either the old or the new positions
+    pub fn save(&mut self) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let mut contents = String::new();
+        for (tag, cursor) in &self.positions {
+            writeln!(contents, "{tag} {} {}", cursor.inode, cursor.offset)
+                .expect("writing to a String can't fail");
+        }
+        let tmp_path = Utf8PathBuf::from(format!("{}.tmp", self.path));
+        fs::write(&tmp_path, contents).with_context(|| format!("write `{tmp_path}`"))?;
+        fs::rename(&tmp_path, &self.path)
+            .with_context(|| format!("replace cursor file `{}`", self.path))?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+fn parse_line(line: &str) -> Result<(&str, Cursor)> {
+    let mut fields = line.split_ascii_whitespace();
+    let (tag, inode, offset) = match (fields.next(), fields.next(), fields.next(), fields.next()) {
+        (Some(tag), Some(inode), Some(offset), None) => (tag, inode, offset),
+        _ => bail!("expected `{{tag}} {{inode}} {{offset}}`"),
+    };
+    let inode = inode.parse().context("invalid inode")?;
+    let offset = offset.parse().context("invalid offset")?;
+    Ok((tag, Cursor { inode, offset }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn test_path(name: &str) -> Utf8PathBuf {
+        let path = Utf8PathBuf::from_path_buf(env::temp_dir())
+            .unwrap()
+            .join(format!("logread-cursors-{}-{name}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn lines_parse() {
+        let (tag, cursor) = parse_line("alice/web 1234 56").unwrap();
+        assert_eq!(tag, "alice/web");
+        assert_eq!(
+            cursor,
+            Cursor {
+                inode: 1234,
+                offset: 56
+            }
+        );
+        assert!(parse_line("  web\t1 2 ").is_ok());
+        for line in ["web 1", "web 1 2 3", "web x 2", "web 1 -2"] {
+            assert!(parse_line(line).is_err(), "{line}");
+        }
+    }
+
+    #[test]
+    fn saved_positions_load() {
+        let path = test_path("roundtrip");
+        let mut cursors = Cursors::load(&path).unwrap();
+        let web = Tag::new("web").unwrap();
+        let db = Tag::new("alice/db").unwrap();
+        assert_eq!(cursors.get(web), None);
+        cursors.update(
+            web,
+            Cursor {
+                inode: 1,
+                offset: 10,
+            },
+        );
+        cursors.update(
+            db,
+            Cursor {
+                inode: 2,
+                offset: 20,
+            },
+        );
+        cursors.update(
+            web,
+            Cursor {
+                inode: 3,
+                offset: 30,
+            },
+        );
+        cursors.save().unwrap();
+
+        let cursors = Cursors::load(&path).unwrap();
+        assert_eq!(
+            cursors.get(web),
+            Some(Cursor {
+                inode: 3,
+                offset: 30
+            })
+        );
+        assert_eq!(
+            cursors.get(db),
+            Some(Cursor {
+                inode: 2,
+                offset: 20
+            })
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn invalid_line_is_reported() {
+        let path = test_path("invalid");
+        fs::write(&path, "web 1 2\n\nweb 1\n").unwrap();
+        let err = Cursors::load(&path).err().unwrap();
+        assert!(format!("{err}").ends_with("line 3"), "{err}");
+        fs::remove_file(&path).unwrap();
+    }
+}
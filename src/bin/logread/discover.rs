@@ -0,0 +1,226 @@
+//! Finding every log for `logread --all`
+//!
+//! The base directory holds a directory per system log and a directory per user with their logs.
+//! Both start out empty, so they're told apart by their contents: a directory containing files is
+//! a system log, the directories inside it are user logs. Directories created while following
+//! are watched until their first log file or user log shows up.
+
+use crate::watcher::{Event, Events, Watch, Watcher};
+use crate::{inotify_limit, parse_log, Follow};
+use anyhow::{Context, Result};
+use camino::Utf8Path as Path;
+use inotify::{EventMask, WatchDescriptor, WatchMask};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use tokio::sync::mpsc;
+use tokio::time::{self, MissedTickBehavior};
+
+/// the logs currently in `base_path`, user logs only with `users`
+pub fn existing_logs(base_path: &Path, users: bool) -> Result<Vec<String>> {
+    let mut logs = Vec::new();
+    for name in dir_names(base_path)? {
+        logs.extend(logs_in(base_path, &name, users));
+    }
+    logs.sort();
+    Ok(logs)
+}
+
+/// names of the directories in the base directory
+fn dir_names(base_path: &Path) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    for dir in base_path.read_dir().context("read log base directory")? {
+        let dir = dir.context("read log base directory")?;
+        if !dir.file_type().is_ok_and(|ty| ty.is_dir()) {
+            continue;
+        }
+        // the log writer only creates UTF-8 names, ignore anything else
+        if let Ok(name) = dir.file_name().into_string() {
+            names.push(name);
+        }
+    }
+    Ok(names)
+}
+
+/// the system log or user logs in the base directory's `name`, unreadable directories are reported
+/// and skipped
+fn logs_in(base_path: &Path, name: &str, users: bool) -> Vec<String> {
+    let dir = base_path.join(name);
+    let mut logs = Vec::new();
+    let mut has_files = false;
+    let entries = match dir.read_dir() {
+        Ok(entries) => entries,
+        Err(err) => {
+            eprintln!("[{dir}] {err}");
+            return logs;
+        }
+    };
+    for entry in entries {
+        let Ok(entry) = entry else { continue };
+        if !entry.file_type().is_ok_and(|ty| ty.is_dir()) {
+            has_files = true;
+        } else if users {
+            if let Ok(sv) = entry.file_name().into_string() {
+                logs.push(format!("{name}/{sv}"));
+            }
+        }
+    }
+    if has_files {
+        logs.push(name.to_owned());
+    }
+    logs.retain(|log| parse_log(log).is_ok());
+    logs
+}
+
+/// Logs found so far, new ones are sent to the printer to be followed
+struct Found {
+    known: HashSet<String>,
+    new: mpsc::UnboundedSender<String>,
+}
+
+impl Found {
+    fn add(&mut self, logs: Vec<String>) {
+        for log in logs {
+            if !self.known.contains(&log) {
+                // the printer is gone when this fails, we'll be aborted soon
+                let _ = self.new.send(log.clone());
+                self.known.insert(log);
+            }
+        }
+    }
+}
+
+/// sends logs created in `base_path` after `known` were found to `new`
+///
+/// uses inotify unless `--poll` is given or the inotify limits are reached, then the base
+/// directory is scanned every `--poll-interval`
+pub async fn watch(
+    base_path: &Path,
+    users: bool,
+    follow: Follow,
+    known: HashSet<String>,
+    new: mpsc::UnboundedSender<String>,
+) -> Result<()> {
+    let mut found = Found { known, new };
+    if let Some(watcher) = follow.watcher {
+        match watch_inotify(watcher, base_path, users, &mut found).await {
+            Err(err) => match err.downcast_ref().and_then(inotify_limit) {
+                Some(sysctl) => eprintln!(
+                    "[{base_path}] inotify limit reached ({err:#}), looking for new logs every \
+                     {:?} instead, raise `{sysctl}` to use inotify",
+                    follow.poll_interval,
+                ),
+                None => return Err(err),
+            },
+            Ok(()) => return Ok(()),
+        }
+    }
+
+    let mut interval = time::interval(follow.poll_interval);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        found.add(existing_logs(base_path, users)?);
+    }
+}
+
+/// watches the base directory for new directories and those for their first log file or user log
+async fn watch_inotify(
+    watcher: &'static Watcher,
+    base_path: &Path,
+    users: bool,
+    found: &mut Found,
+) -> Result<()> {
+    let mut events = watcher.events();
+    let base_watch = events
+        .add(
+            base_path,
+            WatchMask::CREATE | WatchMask::MOVED_TO | WatchMask::ONLYDIR,
+        )
+        .context("watching log base directory")?;
+    let mut dirs = Dirs::default();
+    dirs.rescan(&events, base_path, users, found)?;
+
+    loop {
+        let event = match events.next().await.context("reading inotify event")? {
+            Event::Watched(event) => event,
+            Event::Missed => {
+                dirs.rescan(&events, base_path, users, found)?;
+                continue;
+            }
+        };
+        if event.mask.contains(EventMask::IGNORED) {
+            // the directory was deleted and the kernel removed its watch
+            dirs.watched.remove(&event.wd);
+            continue;
+        }
+        let Some(name) = event.name.as_ref().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        let is_dir = event.mask.contains(EventMask::ISDIR);
+        if event.wd == *base_watch.wd() {
+            if is_dir {
+                dirs.watch(&events, base_path, name, users, found)?;
+            }
+        } else if let Some((dir, _)) = dirs.watched.get(&event.wd) {
+            // any file makes it a system log, directories are user logs
+            let log = if !is_dir {
+                dir.clone()
+            } else if users {
+                format!("{dir}/{name}")
+            } else {
+                continue;
+            };
+            if parse_log(&log).is_ok() {
+                found.add(vec![log]);
+            }
+        }
+    }
+}
+
+/// Watched directories of the base directory
+#[derive(Default)]
+struct Dirs {
+    watched: HashMap<WatchDescriptor, (String, Watch)>,
+}
+
+impl Dirs {
+    /// watches the base directory's `name` and adds the logs already in it
+    fn watch(
+        &mut self,
+        events: &Events,
+        base_path: &Path,
+        name: &str,
+        users: bool,
+        found: &mut Found,
+    ) -> Result<()> {
+        let mask = WatchMask::CREATE | WatchMask::MOVED_TO | WatchMask::ONLYDIR;
+        let watch = match events.add(base_path.join(name), mask) {
+            Ok(watch) => watch,
+            // removed again before we got to it
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err).with_context(|| format!("watching `{name}`")),
+        };
+        self.watched
+            .insert(watch.wd().clone(), (name.to_owned(), watch));
+        // whatever was created before the watch
+        found.add(logs_in(base_path, name, users));
+        Ok(())
+    }
+
+    /// watches every directory in the base directory and finds the logs in them, at the start and
+    /// after events were missed, directories which are gone are forgotten
+    fn rescan(
+        &mut self,
+        events: &Events,
+        base_path: &Path,
+        users: bool,
+        found: &mut Found,
+    ) -> Result<()> {
+        let names = dir_names(base_path)?;
+        self.watched.retain(|_, (name, _)| names.contains(name));
+        for name in names {
+            self.watch(events, base_path, &name, users, found)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,1304 @@
+mod cursor;
+mod discover;
+mod queue;
+mod remote;
+mod stats;
+mod watcher;
+
+use std::ffi::CStr;
+use std::io::{self, ErrorKind, SeekFrom, Write};
+use std::mem::MaybeUninit;
+use std::os::unix::fs::MetadataExt;
+use std::{env, ptr};
+
+use anyhow::{ensure, Context, Result};
+use camino::{Utf8Path as Path, Utf8PathBuf};
+use clap::{ArgEnum, Parser};
+use cursor::{Cursor, Cursors};
+use inotify::WatchMask;
+use queue::Overflow;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::fmt::{self, Display};
+use std::hash::{Hash, Hasher};
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use std::time::Duration;
+use svmgr::cli::parse_duration;
+use svmgr::config::{InvalidUnitName, UnitName};
+use svmgr::log::{
+    self, LogEntry, LogFile, LogReader, ReadCounters, ReadEntryError, TimestampFormat,
+};
+use svmgr::signal;
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncSeekExt};
+use tokio::sync::mpsc;
+use tokio::task;
+use tokio::time::{self, Instant, MissedTickBehavior};
+use watcher::{Event, Events, Watch, Watcher};
+
+#[derive(Parser)]
+struct Args {
+    /// Tail the logs instead of printing all entries
+    #[clap(short, long)]
+    follow: bool,
+
+    /// Exit after no new entries arrived for this long, e.g. `30s`
+    ///
+    /// The timeout is shared by all logs, `0` or unset waits forever.
+    #[clap(long, parse(try_from_str = parse_duration))]
+    idle_timeout: Option<Duration>,
+
+    /// Exit with an error if any of the requested logs was invalid or missing
+    #[clap(long)]
+    strict: bool,
+
+    /// Don't print warnings about the requested logs
+    #[clap(short, long)]
+    quiet: bool,
+
+    /// Number of entries queued per log while the output catches up
+    #[clap(long, default_value = "1")]
+    buffer: NonZeroUsize,
+
+    /// What to do when a log's queue is full
+    ///
+    /// `block` slows down reading that log, a slow output slows down all of them. `drop-oldest`
+    /// keeps reading and reports how many entries were dropped.
+    #[clap(long, arg_enum, default_value = "block")]
+    overflow: Overflow,
+
+    /// Periodically print read throughput of every log to stderr
+    #[clap(long)]
+    stats: bool,
+
+    /// How often `--stats` prints, e.g. `10s`
+    #[clap(long, default_value = "5s", parse(try_from_str = parse_duration))]
+    stats_interval: Duration,
+
+    /// Resume following from the positions saved in this file and keep them updated
+    ///
+    /// Every log is read from where the previous run stopped and then followed live, logs without
+    /// a saved position are read from the start of their current file. Positions are saved after
+    /// the entries were written to stdout, so an interrupted run may repeat entries but never
+    /// misses any.
+    #[clap(long, requires = "follow")]
+    from_cursor: Option<Utf8PathBuf>,
+
+    /// Print the serialized entries instead of text, for piping into `logwrite --raw-in`
+    ///
+    /// The entries keep their original timestamps, a header frame is printed before the first
+    /// entry and whenever the timestamp format changes. The tags are not part of the output, so
+    /// this is meant for reading a single log.
+    #[clap(long)]
+    raw: bool,
+
+    /// Check the followed logs for new entries periodically instead of using inotify
+    ///
+    /// For filesystems where inotify doesn't work, like some network mounts. Applies to `--follow`
+    /// and `--serve`, polling is used anyway when the inotify limits are reached.
+    #[clap(long)]
+    poll: bool,
+
+    /// How often polling checks the logs, e.g. `500ms`
+    #[clap(long, default_value = "1s", parse(try_from_str = parse_duration))]
+    poll_interval: Duration,
+
+    /// Follow the logs on another host's `logread --serve` at `host:port`
+    ///
+    /// The connection is reestablished when it breaks, entries written meanwhile are missed. Only
+    /// new entries are read, user logs have to be requested as `{user}/{tag}`.
+    #[clap(long, requires = "follow", conflicts_with = "from-cursor")]
+    remote: Option<String>,
+
+    /// Serve the logs to `logread --remote` on this address, e.g. `127.0.0.1:7000`
+    ///
+    /// Anyone who can connect can read every log, there is no authentication or encryption. Listen
+    /// on localhost and use an SSH tunnel to reach it from other hosts.
+    #[clap(long, conflicts_with_all = &["follow", "remote", "logs", "all"])]
+    serve: Option<String>,
+
+    /// Read every system log, with `--follow` also the ones created while running
+    #[clap(long, conflicts_with_all = &["logs", "remote"])]
+    all: bool,
+
+    /// With `--all`, read every user's logs as well
+    #[clap(long, requires = "all")]
+    user: bool,
+
+    /// When to color the tags, `auto` colors them if stdout is a terminal
+    #[clap(long, arg_enum, default_value = "auto")]
+    color: Color,
+
+    /// Which logs to read
+    ///
+    /// User logs are specified as `{user}/{tag}`, system logs just `{tag}`. A bare `{tag}` which
+    /// only exists as a log of the invoking user resolves to that.
+    logs: Vec<String>,
+}
+
+impl Args {
+    fn follow_options(&self) -> Result<Follow> {
+        // a zero interval would spin
+        let poll_interval = self.poll_interval.max(Duration::from_millis(10));
+        let following = self.follow || self.serve.is_some();
+        let watcher = match following && !self.poll {
+            true => shared_watcher(poll_interval)?,
+            false => None,
+        };
+        Ok(Follow {
+            raw: self.raw,
+            watcher,
+            poll_interval,
+        })
+    }
+}
+
+/// When the tags are colored
+#[derive(ArgEnum, Clone, Copy)]
+enum Color {
+    Auto,
+    Always,
+    Never,
+}
+
+impl Color {
+    fn enabled(self) -> bool {
+        match self {
+            // SAFETY: `isatty` only inspects the file descriptor
+            Color::Auto => unsafe { libc::isatty(libc::STDOUT_FILENO) == 1 },
+            Color::Always => true,
+            Color::Never => false,
+        }
+    }
+}
+
+/// ANSI colors for the tags, each tag always gets the same one
+const TAG_COLORS: [u8; 12] = [31, 32, 33, 34, 35, 36, 91, 92, 93, 94, 95, 96];
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct Tag {
+    user: Option<&'static str>,
+    sv: &'static str,
+}
+
+impl Display for Tag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.user, self.sv) {
+            (Some(user), sv) => f.write_fmt(format_args!("{user}/{sv}")),
+            (None, sv) => f.write_fmt(format_args!("{sv}")),
+        }
+    }
+}
+
+impl Tag {
+    /// parses `{user}/{tag}` or `{tag}`, both parts have to be valid unit names
+    fn new(log: &str) -> Result<Tag, InvalidUnitName> {
+        let (user, sv) = parse_log(log)?;
+        Ok(Tag {
+            user: user.map(|user| &*Box::leak(Box::from(user.as_str()))),
+            sv: Box::leak(Box::from(sv.as_str())),
+        })
+    }
+
+    fn color(&self) -> u8 {
+        let mut hasher = DefaultHasher::new();
+        self.hash(&mut hasher);
+        TAG_COLORS[(hasher.finish() % TAG_COLORS.len() as u64) as usize]
+    }
+
+    fn path(&self, base_path: &Path) -> Utf8PathBuf {
+        match self.user {
+            Some(user) => base_path.join(user).join(self.sv),
+            None => base_path.join(self.sv),
+        }
+    }
+
+    /// resolves a bare `{tag}` to the system log, the invoking user's log or both if both exist
+    fn resolve(
+        self,
+        base_path: &Path,
+        user: Option<&'static str>,
+        warnings: &mut Warnings,
+    ) -> Vec<Tag> {
+        let user = match (self.user, user) {
+            (None, Some(user)) => user,
+            // explicit user log or we don't know who we are
+            _ => return vec![self],
+        };
+        let user_tag = Tag {
+            user: Some(user),
+            sv: self.sv,
+        };
+        match (
+            self.path(base_path).exists(),
+            user_tag.path(base_path).exists(),
+        ) {
+            (true, true) => {
+                warnings.warn(format_args!(
+                    "`{self}` is ambiguous, following both `{self}` and `{user_tag}`"
+                ));
+                vec![self, user_tag]
+            }
+            (false, true) => vec![user_tag],
+            // report the system log as missing if neither exists
+            (_, false) => vec![self],
+        }
+    }
+}
+
+/// splits `{user}/{tag}` or `{tag}` into unit names, without leaking them like [`Tag::new`]
+fn parse_log(log: &str) -> Result<(Option<UnitName>, UnitName), InvalidUnitName> {
+    Ok(match log.split_once('/') {
+        Some((user, sv)) => (Some(UnitName::new(user)?), UnitName::new(sv)?),
+        None => (None, UnitName::new(log)?),
+    })
+}
+
+/// Reports problems with the requested logs and remembers whether there were any
+struct Warnings {
+    quiet: bool,
+    occurred: bool,
+}
+
+impl Warnings {
+    fn warn(&mut self, message: fmt::Arguments<'_>) {
+        self.occurred = true;
+        if !self.quiet {
+            eprintln!("{message}");
+        }
+    }
+}
+
+/// name of the user running `logread`
+fn current_user() -> Option<&'static str> {
+    // SAFETY: `getuid` is always successful
+    let uid = unsafe { libc::getuid() };
+    let mut passwd = MaybeUninit::<libc::passwd>::uninit();
+    let mut buffer = vec![0 as libc::c_char; 4096];
+    let mut result = ptr::null_mut();
+    // SAFETY: all pointers are valid for the given lengths
+    let ret = unsafe {
+        libc::getpwuid_r(
+            uid,
+            passwd.as_mut_ptr(),
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    let name = if ret == 0 && !result.is_null() {
+        // SAFETY: on success `result` points to `passwd` whose strings live in `buffer`
+        let name = unsafe { CStr::from_ptr((*result).pw_name) };
+        name.to_str().ok()?.to_owned()
+    } else {
+        env::var("USER").ok()?
+    };
+    Some(Box::leak(Box::from(name)))
+}
+
+struct TaggedLogEntry {
+    tag: Tag,
+    entry: Payload,
+    /// position after the entry, only known when following
+    cursor: Option<Cursor>,
+}
+
+enum Payload {
+    Decoded(LogEntry<'static>),
+    /// serialized frame with `--raw`
+    Raw {
+        frame: Vec<u8>,
+        format: Arc<TimestampFormat>,
+    },
+}
+
+/// How followed logs are read
+#[derive(Clone, Copy)]
+struct Follow {
+    raw: bool,
+    /// waits for new entries, `None` to check every `poll_interval` instead
+    watcher: Option<&'static Watcher>,
+    poll_interval: Duration,
+}
+
+/// Where following a log begins
+#[derive(Clone, Copy)]
+enum Start {
+    /// only new entries
+    End,
+    /// all entries of the current file
+    Beginning,
+    /// where a previous run stopped
+    At(Cursor),
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+
+    // must happen before any tasks spawn threads for blocking file operations
+    let mut signals =
+        signal::forward(&[signal::SIGINT, signal::SIGTERM]).context("install signal handling")?;
+
+    let base_path: &'static Path = Box::leak(log::base_dir().into_boxed_path());
+    let follow = args.follow_options()?;
+    if let Some(addr) = &args.serve {
+        let follow = Follow {
+            raw: true,
+            ..follow
+        };
+        return remote::serve(addr, base_path, follow, &mut signals).await;
+    }
+
+    let logs = match args.all {
+        true => discover::existing_logs(base_path, args.user)?,
+        false => args.logs.clone(),
+    };
+    // with `--all --follow` logs may still appear
+    let discover = args.all && args.follow;
+    if logs.is_empty() && !discover {
+        return Ok(());
+    }
+
+    if args.stats {
+        stats::enable();
+    }
+    let mut stats_printer = stats::Printer::default();
+    let mut stats_tick = time::interval(args.stats_interval.max(Duration::from_millis(100)));
+
+    let mut cursors = args.from_cursor.as_deref().map(Cursors::load).transpose()?;
+    let mut cursor_tick = time::interval(Duration::from_secs(1));
+
+    let mut rx = queue::Receiver::new(args.buffer, args.overflow);
+
+    let user = current_user();
+    let mut warnings = Warnings {
+        quiet: args.quiet,
+        occurred: false,
+    };
+    let mut tasks = Vec::new();
+    for log in &logs {
+        let tag = match Tag::new(log) {
+            Ok(tag) => tag,
+            Err(err) => {
+                warnings.warn(format_args!("{err}"));
+                continue;
+            }
+        };
+        if let Some(addr) = &args.remote {
+            let addr = addr.clone();
+            let tx = rx.sender();
+            let raw = args.raw;
+            tasks.push(task::spawn(async move {
+                remote::follow(&addr, tag, raw, &tx).await
+            }));
+            continue;
+        }
+        // discovered logs are exactly what they're named
+        let tags = match args.all {
+            true => vec![tag],
+            false => tag.resolve(base_path, user, &mut warnings),
+        };
+        for tag in tags {
+            let path = tag.path(base_path);
+            if !path.exists() {
+                warnings.warn(format_args!("[{path}] does not exist"));
+                continue;
+            }
+            let tx = rx.sender();
+            let raw = args.raw;
+            tasks.push(if args.follow {
+                let start = match &cursors {
+                    Some(cursors) => cursors.get(tag).map_or(Start::Beginning, Start::At),
+                    None => Start::End,
+                };
+                task::spawn(async move { tail_log(tag, &path, start, follow, &tx).await })
+            } else {
+                task::spawn_blocking(move || read_history(tag, &path, raw, &tx))
+            });
+        }
+    }
+
+    let (new_tx, mut new_logs) = mpsc::unbounded_channel();
+    // keeps the printer waiting for logs to follow even when there are none yet
+    let _discover_tx = discover.then(|| rx.sender());
+    if discover {
+        let users = args.user;
+        let known = logs.into_iter().collect::<HashSet<_>>();
+        tasks.push(task::spawn(async move {
+            if let Err(err) = discover::watch(base_path, users, follow, known, new_tx).await {
+                eprintln!("[{base_path}] {err:#}, not looking for new logs anymore");
+            }
+        }));
+    }
+
+    let idle_timeout = args.idle_timeout.filter(|timeout| !timeout.is_zero());
+    let idle = time::sleep(idle_timeout.unwrap_or_default());
+    tokio::pin!(idle);
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    // format of the last raw header printed
+    let mut raw_format = None;
+    let color = !args.raw && args.color.enabled();
+    let mut interrupted = false;
+    loop {
+        tokio::select! {
+            log_entry = rx.recv() => match log_entry {
+                Some((log_entry, dropped)) => {
+                    print_received(
+                        &mut stdout,
+                        &log_entry,
+                        dropped,
+                        &mut raw_format,
+                        color,
+                        &mut cursors,
+                    )?;
+                    if let Some(timeout) = idle_timeout {
+                        idle.as_mut().reset(Instant::now() + timeout);
+                    }
+                }
+                None => break,
+            },
+            Some(log) = new_logs.recv(), if discover => {
+                // discovery only sends valid names
+                if let Ok(tag) = Tag::new(&log) {
+                    let path = tag.path(base_path);
+                    let tx = rx.sender();
+                    // everything in a new log is new
+                    tasks.push(task::spawn(async move {
+                        tail_log(tag, &path, Start::Beginning, follow, &tx).await
+                    }));
+                }
+            }
+            _ = &mut idle, if idle_timeout.is_some() => break,
+            _ = stats_tick.tick(), if args.stats => stats_printer.print(),
+            _ = cursor_tick.tick(), if cursors.is_some() => {
+                save_cursors(&mut stdout, &mut cursors)?;
+            }
+            _ = signals.recv() => {
+                interrupted = true;
+                break;
+            }
+        }
+    }
+
+    // stop reading new entries, what was read already is still printed
+    for task in &tasks {
+        task.abort();
+    }
+    while let Some((log_entry, dropped)) = rx.try_recv() {
+        print_received(
+            &mut stdout,
+            &log_entry,
+            dropped,
+            &mut raw_format,
+            color,
+            &mut cursors,
+        )?;
+    }
+    if interrupted && !args.raw {
+        // leave the terminal on a fresh line after the `^C`
+        writeln!(stdout).context("write stdout")?;
+    }
+    stdout.flush().context("flush stdout")?;
+    save_cursors(&mut stdout, &mut cursors)?;
+
+    ensure!(
+        !(args.strict && warnings.occurred),
+        "some of the requested logs were invalid or missing"
+    );
+    Ok(())
+}
+
+/// prints an entry taken from the queues and records its position for the cursors
+fn print_received(
+    out: &mut impl Write,
+    log_entry: &TaggedLogEntry,
+    dropped: u64,
+    raw_format: &mut Option<Arc<TimestampFormat>>,
+    color: bool,
+    cursors: &mut Option<Cursors>,
+) -> Result<()> {
+    if dropped > 0 {
+        eprintln!(
+            "[{}] dropped {dropped} entries, output couldn't keep up",
+            log_entry.tag
+        );
+    }
+    print_entry(out, log_entry, raw_format, color).context("write stdout")?;
+    if let (Some(cursors), Some(cursor)) = (cursors, log_entry.cursor) {
+        cursors.update(log_entry.tag, cursor);
+    }
+    Ok(())
+}
+
+/// prints an entry as text or as its frame, raw frames are preceded by a header whenever the
+/// timestamp format differs from `raw_format`. `color` colors the tags of text entries
+fn print_entry(
+    out: &mut impl Write,
+    log_entry: &TaggedLogEntry,
+    raw_format: &mut Option<Arc<TimestampFormat>>,
+    color: bool,
+) -> io::Result<()> {
+    let entry = match &log_entry.entry {
+        Payload::Decoded(entry) => entry,
+        Payload::Raw { frame, format } => {
+            if raw_format.as_ref() != Some(format) {
+                let mut header = Vec::new();
+                format.serialize_header(&mut header);
+                out.write_all(&header)?;
+                *raw_format = Some(Arc::clone(format));
+            }
+            return out.write_all(frame);
+        }
+    };
+    let tag = log_entry.tag;
+    let timestamp = entry.local_timestamp().format("%Y-%m-%d %H:%M:%S.%3f");
+    let entry = String::from_utf8_lossy(entry.as_slice());
+    for line in entry.lines() {
+        if color {
+            writeln!(out, "{timestamp} \x1b[{}m{tag}\x1b[0m {line}", tag.color())?;
+        } else {
+            writeln!(out, "{timestamp} {tag} {line}")?;
+        }
+    }
+    Ok(())
+}
+
+/// the reader's timestamp format, the same `Arc` is handed out until the format changes
+fn shared_format(
+    log_reader: &LogReader,
+    shared: &mut Option<Arc<TimestampFormat>>,
+) -> Arc<TimestampFormat> {
+    let format = log_reader.format();
+    match shared {
+        Some(shared) if **shared == *format => Arc::clone(shared),
+        _ => Arc::clone(shared.insert(Arc::new(format.clone()))),
+    }
+}
+
+/// saves the read positions, the entries before them must reach stdout first
+fn save_cursors(stdout: &mut impl Write, cursors: &mut Option<Cursors>) -> Result<()> {
+    if let Some(cursors) = cursors {
+        stdout.flush().context("flush stdout")?;
+        cursors.save()?;
+    }
+    Ok(())
+}
+
+/// prints all entries from the rotated log files and `current`, compressed files are decompressed
+/// transparently. files which fail to read are reported and skipped.
+fn read_history(tag: Tag, path: &Path, raw: bool, tx: &queue::Sender<TaggedLogEntry>) {
+    let mut files = match history_files(path) {
+        Ok(files) => files,
+        Err(err) => {
+            eprintln!("[{path}] {err:?}");
+            return;
+        }
+    };
+    files.push(path.join("current"));
+
+    let mut format = None;
+    for file in files {
+        let log_file = match LogFile::open(&file) {
+            Ok(log_file) => log_file,
+            Err(err) if err.kind() == ErrorKind::NotFound => continue,
+            Err(err) => {
+                eprintln!("[{file}] {err}");
+                continue;
+            }
+        };
+        let mut entries = log::iter_entries(log_file);
+        let mut recorded = ReadCounters::default();
+        loop {
+            let entry = if raw {
+                entries.next_raw().map(|frame| {
+                    frame.map(|frame| Payload::Raw {
+                        frame,
+                        format: shared_format(entries.log_reader(), &mut format),
+                    })
+                })
+            } else {
+                entries.next().map(|entry| entry.map(Payload::Decoded))
+            };
+            let Some(entry) = entry else { break };
+            stats::record(tag, entries.log_reader().counters(), &mut recorded);
+            match entry {
+                Ok(entry) => {
+                    let tagged = TaggedLogEntry {
+                        tag,
+                        entry,
+                        cursor: None,
+                    };
+                    if tx.blocking_send(tagged).is_err() {
+                        return;
+                    }
+                }
+                Err(ReadEntryError::DeserializeError(err)) => {
+                    eprintln!("[{file}] skipping corrupt entry: {err}");
+                }
+                // the iteration ends after it, e.g. when decompressing failed
+                Err(ReadEntryError::IoError(err)) => eprintln!("[{file}] failed to read: {err}"),
+            }
+        }
+        stats::record(tag, entries.log_reader().counters(), &mut recorded);
+        warn_skipped(&file, entries.log_reader(), (0, 0));
+    }
+}
+
+/// warns about data the reader discarded since the `(garbage_bytes, false_starts)` snapshot
+fn warn_skipped(source: &dyn Display, log_reader: &LogReader, since: (u64, u64)) {
+    let garbage_bytes = log_reader.garbage_bytes - since.0;
+    let false_starts = log_reader.false_starts - since.1;
+    if garbage_bytes > 0 {
+        eprintln!(
+            "[{source}] skipped {garbage_bytes} bytes of unrecognized data \
+             ({false_starts} truncated entries)"
+        );
+    }
+}
+
+/// rotated log files in the log directory, oldest first
+fn history_files(path: &Path) -> Result<Vec<Utf8PathBuf>> {
+    let mut files = Vec::new();
+    for dir_entry in path.read_dir().context("read log directory")? {
+        let dir_entry = dir_entry.context("read log directory")?;
+        if dir_entry.file_name() == "current" {
+            continue;
+        }
+        if !dir_entry.file_type().is_ok_and(|ty| ty.is_file()) {
+            continue;
+        }
+        // the log writer only creates UTF-8 names, ignore anything else
+        if let Ok(file) = Utf8PathBuf::from_path_buf(dir_entry.path()) {
+            files.push(file);
+        }
+    }
+    // rotated files are named so they sort chronologically
+    files.sort();
+    Ok(files)
+}
+
+async fn tail_log(
+    tag: Tag,
+    path: &Path,
+    start: Start,
+    follow: Follow,
+    tx: &queue::Sender<TaggedLogEntry>,
+) {
+    for _ in 0..3 {
+        // TODO better retry limit strategy
+        match try_tail_log(tag, path, start, follow, tx).await {
+            Err(err) if err.is::<queue::Disconnected>() => return,
+            Err(err) => eprintln!("[{path}] {err:?}"),
+            Ok(()) => {}
+        }
+    }
+}
+
+/// tries to register an inotify watch first for the current log file and hand over to `tail_file`,
+/// if it's not found it tries watching the parent directory and hands over to `wait_for_file`.
+/// with `--poll` or when the inotify limits are reached it hands over to `poll_file`
+async fn try_tail_log(
+    tag: Tag,
+    path: &Path,
+    start: Start,
+    follow: Follow,
+    tx: &queue::Sender<TaggedLogEntry>,
+) -> Result<()> {
+    let current_path = path.join("current");
+    let Some(watcher) = follow.watcher else {
+        return poll_file(tag, &current_path, start, follow, tx).await;
+    };
+    let events = watcher.events();
+    let watches = events
+        .add(&current_path, WatchMask::MODIFY)
+        .and_then(|file_watch| {
+            let dir_watch = events.add(path, WatchMask::CREATE | WatchMask::MOVED_TO)?;
+            Ok((file_watch, dir_watch))
+        });
+    match watches {
+        Ok((file_watch, dir_watch)) => {
+            let watches = Watches {
+                events,
+                file_watch,
+                dir_watch,
+            };
+            tail_file(tag, &current_path, start, follow.raw, tx, watches).await
+        }
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            let dir_watch = events
+                .add(path, WatchMask::CREATE | WatchMask::MOVED_TO)
+                .context("watching log directory")?;
+            wait_for_file(tag, &current_path, follow.raw, tx, events, dir_watch).await
+        }
+        Err(err) => match inotify_limit(&err) {
+            Some(sysctl) => {
+                eprintln!(
+                    "[{tag}] inotify limit reached ({err}), polling every {:?} instead, raise \
+                     `{sysctl}` to use inotify",
+                    follow.poll_interval,
+                );
+                poll_file(tag, &current_path, start, follow, tx).await
+            }
+            None => Err(err).context("watching log file"),
+        },
+    }
+}
+
+/// the sysctl limiting inotify if `err` says the limit was reached
+fn inotify_limit(err: &io::Error) -> Option<&'static str> {
+    match err.raw_os_error()? {
+        libc::ENOSPC => Some("fs.inotify.max_user_watches"),
+        libc::EMFILE => Some("fs.inotify.max_user_instances"),
+        _ => None,
+    }
+}
+
+/// the inotify instance every followed log and the discovery of new logs share, `None` when the
+/// instance limit is reached and everything is checked every `poll_interval` instead
+fn shared_watcher(poll_interval: Duration) -> Result<Option<&'static Watcher>> {
+    match Watcher::init() {
+        Ok(watcher) => Ok(Some(watcher)),
+        Err(err) => match inotify_limit(&err) {
+            Some(sysctl) => {
+                eprintln!(
+                    "inotify limit reached ({err}), polling every {poll_interval:?} instead, \
+                     raise `{sysctl}` to use inotify",
+                );
+                Ok(None)
+            }
+            None => Err(err).context("inotify init"),
+        },
+    }
+}
+
+/// Inotify watches of a log file and its directory
+struct Watches {
+    events: Events,
+    file_watch: Watch,
+    dir_watch: Watch,
+}
+
+/// tail a log file. reads `LogEntry`s when the file is modified or replaced with a new file
+///
+/// the watch on the file follows its inode, so the directory is watched as well and the inode at
+/// `path` is compared with the open file on every event. this catches any rotation scheme which
+/// ends with a new file at `path`, not only renaming one onto it.
+async fn tail_file(
+    tag: Tag,
+    path: &Path,
+    start: Start,
+    raw: bool,
+    tx: &queue::Sender<TaggedLogEntry>,
+    watches: Watches,
+) -> Result<()> {
+    let Watches {
+        mut events,
+        mut file_watch,
+        dir_watch,
+    } = watches;
+    let file_name = path.file_name().context("log file has no name")?;
+
+    let mut followed = FollowedFile::open(tag, path, start, raw, tx).await?;
+    loop {
+        if let Event::Watched(event) = events.next().await.context("reading inotify event")? {
+            if event.wd == *dir_watch.wd() && event.name.as_deref() != Some(file_name.as_ref()) {
+                // some other file in the log directory
+                continue;
+            }
+        }
+        if followed.update(tag, path, raw, tx).await? {
+            // the old file may already be gone together with its watch
+            drop(file_watch);
+            file_watch = events
+                .add(path, WatchMask::MODIFY)
+                .context("watching new log file")?;
+        }
+    }
+}
+
+/// like `tail_file` but checks the file every `--poll-interval` instead of waiting for inotify
+async fn poll_file(
+    tag: Tag,
+    path: &Path,
+    start: Start,
+    follow: Follow,
+    tx: &queue::Sender<TaggedLogEntry>,
+) -> Result<()> {
+    let mut interval = time::interval(follow.poll_interval);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut start = start;
+    while !path.exists() {
+        // like `wait_for_file`, a file created later is read from the start
+        start = Start::Beginning;
+        interval.tick().await;
+    }
+    let mut followed = FollowedFile::open(tag, path, start, follow.raw, tx).await?;
+    loop {
+        interval.tick().await;
+        followed.update(tag, path, follow.raw, tx).await?;
+    }
+}
+
+/// The log file being followed and how far it was read
+struct FollowedFile {
+    file: File,
+    inode: u64,
+    log_reader: LogReader,
+    /// where we finished reading, when the length increases we'll read the difference
+    position: u64,
+}
+
+impl FollowedFile {
+    /// opens the file, positions it at `start` and reads what's already there from that point
+    async fn open(
+        tag: Tag,
+        path: &Path,
+        start: Start,
+        raw: bool,
+        tx: &queue::Sender<TaggedLogEntry>,
+    ) -> Result<FollowedFile> {
+        let dir = path.parent().context("log file has no directory")?;
+        let mut file = File::open(path).await.context("opening log file")?;
+        let inode = file
+            .metadata()
+            .await
+            .context("read log file metadata")?
+            .ino();
+        let mut log_reader = LogReader::new();
+        // we skip the start of the file, pick up the timestamp format from the header first
+        log_reader
+            .read_header(&mut file)
+            .await
+            .context("read log file header")?;
+        let position = match start {
+            Start::End => file.seek(SeekFrom::End(0)).await.context("seek log file")?,
+            Start::Beginning => file
+                .seek(SeekFrom::Start(0))
+                .await
+                .context("seek log file")?,
+            Start::At(cursor) => resume(tag, dir, cursor, inode, &mut file, raw, tx).await?,
+        };
+        let mut followed = FollowedFile {
+            file,
+            inode,
+            log_reader,
+            position,
+        };
+        // catch up with what's already there before waiting for changes
+        followed.read(tag, raw, tx).await?;
+        Ok(followed)
+    }
+
+    /// reads from the position up to the end of the file
+    async fn read(
+        &mut self,
+        tag: Tag,
+        raw: bool,
+        tx: &queue::Sender<TaggedLogEntry>,
+    ) -> Result<()> {
+        let FollowedFile {
+            file,
+            inode,
+            log_reader,
+            position,
+        } = self;
+        *position += read_entries(tag, log_reader, file, *position, *inode, raw, tx)
+            .await
+            .context("log entries")?;
+        Ok(())
+    }
+
+    /// reads new entries and switches to the file at `path` if it was replaced, returns whether it
+    /// was
+    async fn update(
+        &mut self,
+        tag: Tag,
+        path: &Path,
+        raw: bool,
+        tx: &queue::Sender<TaggedLogEntry>,
+    ) -> Result<bool> {
+        // looked up before reading, so everything written before a rotation we act on is read
+        let current_inode = match tokio::fs::metadata(path).await {
+            Ok(metadata) => Some(metadata.ino()),
+            // moved away and the new file wasn't created yet
+            Err(err) if err.kind() == ErrorKind::NotFound => None,
+            Err(err) => return Err(err).context("read log file metadata"),
+        };
+        // whatever happened, first read what's left in the file we have open, a replaced file
+        // may still have had entries written before it was replaced
+        let metadata = self
+            .file
+            .metadata()
+            .await
+            .context("read log file metadata")?;
+        ensure!(metadata.len() >= self.position, "log file was truncated");
+        self.read(tag, raw, tx).await?;
+
+        if current_inode.is_none_or(|inode| inode == self.inode) {
+            return Ok(false);
+        }
+
+        // a different file is at `path` now, follow it from the start
+        self.file = File::open(path).await.context("opening new log file")?;
+        self.inode = self
+            .file
+            .metadata()
+            .await
+            .context("read log file metadata")?
+            .ino();
+        self.log_reader = LogReader::new();
+        self.position = 0;
+        self.read(tag, raw, tx).await?;
+        Ok(true)
+    }
+}
+
+/// resumes reading at `cursor`, returns the position in `file` to continue at
+///
+/// when `current` was rotated since the cursor was saved, the rest of the rotated file is read
+/// first if it's still there, then `file` is read from the start
+async fn resume(
+    tag: Tag,
+    dir: &Path,
+    cursor: Cursor,
+    inode: u64,
+    file: &mut File,
+    raw: bool,
+    tx: &queue::Sender<TaggedLogEntry>,
+) -> Result<u64> {
+    if cursor.inode == inode {
+        let len = file
+            .metadata()
+            .await
+            .context("read log file metadata")?
+            .len();
+        if cursor.offset <= len {
+            return file
+                .seek(SeekFrom::Start(cursor.offset))
+                .await
+                .context("seek log file");
+        }
+        eprintln!("[{tag}] log file is shorter than the saved position, reading it from the start");
+    } else {
+        match find_rotated(dir, cursor.inode)? {
+            Some(rotated) => read_rotated(tag, &rotated, cursor, raw, tx)
+                .await
+                .with_context(|| format!("read rest of `{rotated}`"))?,
+            None => eprintln!(
+                "[{tag}] log file of the saved position was rotated away, \
+                 reading the current file from the start"
+            ),
+        }
+    }
+    file.seek(SeekFrom::Start(0)).await.context("seek log file")
+}
+
+/// rotated log file with `inode`, compressed files are new files and never match
+fn find_rotated(dir: &Path, inode: u64) -> Result<Option<Utf8PathBuf>> {
+    for file in history_files(dir)? {
+        match std::fs::metadata(&file) {
+            Ok(metadata) if metadata.ino() == inode => return Ok(Some(file)),
+            _ => continue,
+        }
+    }
+    Ok(None)
+}
+
+/// reads the entries of a rotated file after `cursor`
+async fn read_rotated(
+    tag: Tag,
+    path: &Path,
+    cursor: Cursor,
+    raw: bool,
+    tx: &queue::Sender<TaggedLogEntry>,
+) -> Result<()> {
+    let mut file = File::open(path).await.context("opening log file")?;
+    let mut log_reader = LogReader::new();
+    log_reader
+        .read_header(&mut file)
+        .await
+        .context("read log file header")?;
+    let position = file
+        .seek(SeekFrom::Start(cursor.offset))
+        .await
+        .context("seek log file")?;
+    read_entries(
+        tag,
+        &mut log_reader,
+        &mut file,
+        position,
+        cursor.inode,
+        raw,
+        tx,
+    )
+    .await
+    .context("log entries")?;
+    Ok(())
+}
+
+/// reads the entries from `position` up to the end of the file and returns how many bytes were read
+async fn read_entries(
+    tag: Tag,
+    log_reader: &mut LogReader,
+    file: &mut File,
+    position: u64,
+    inode: u64,
+    raw: bool,
+    tx: &queue::Sender<TaggedLogEntry>,
+) -> Result<u64> {
+    log_reader.read_total = 0;
+    log_reader.incomplete = false;
+    let skipped = (log_reader.garbage_bytes, log_reader.false_starts);
+    let mut recorded = log_reader.counters();
+    let mut format = None;
+
+    loop {
+        // counts what the previous iteration read
+        stats::record(tag, log_reader.counters(), &mut recorded);
+        match next_payload(log_reader, file, raw, &mut format).await {
+            Ok(entry) => {
+                let cursor = Cursor {
+                    inode,
+                    offset: position + log_reader.read_total - log_reader.unconsumed(),
+                };
+                let tagged = TaggedLogEntry {
+                    tag,
+                    entry,
+                    cursor: Some(cursor),
+                };
+                tx.send(tagged).await?;
+            }
+            Err(ReadEntryError::DeserializeError(err)) => {
+                eprintln!("[{tag}] skipping corrupt entry: {err}");
+            }
+            Err(err) => {
+                if log_reader.incomplete {
+                    break;
+                } else {
+                    return Err(err).context("read log entry");
+                }
+            }
+        }
+    }
+
+    stats::record(tag, log_reader.counters(), &mut recorded);
+    warn_skipped(&tag, log_reader, skipped);
+    Ok(log_reader.read_total)
+}
+
+/// reads the next entry, decoded or as its frame with `raw`
+async fn next_payload<R: AsyncRead + Unpin>(
+    log_reader: &mut LogReader,
+    reader: &mut R,
+    raw: bool,
+    format: &mut Option<Arc<TimestampFormat>>,
+) -> Result<Payload, ReadEntryError> {
+    if raw {
+        let frame = log_reader.next_raw_entry(reader).await?.to_vec();
+        Ok(Payload::Raw {
+            frame,
+            format: shared_format(log_reader, format),
+        })
+    } else {
+        let entry = log_reader.next_entry(reader).await?;
+        Ok(Payload::Decoded(entry.to_owned()))
+    }
+}
+
+/// watches a directory until the current log file is created, then hands over to `tail_file`
+/// which reads it from the start
+async fn wait_for_file(
+    tag: Tag,
+    path: &Path,
+    raw: bool,
+    tx: &queue::Sender<TaggedLogEntry>,
+    mut events: Events,
+    dir_watch: Watch,
+) -> Result<()> {
+    let file_name = path.file_name().context("log file has no name")?;
+    // it may have been created before the watch
+    while !path.exists() {
+        match events.next().await.context("reading inotify event")? {
+            Event::Watched(event) if event.name.as_deref() == Some(file_name.as_ref()) => break,
+            // checked again by the loop
+            Event::Watched(_) | Event::Missed => {}
+        }
+    }
+
+    let file_watch = events
+        .add(path, WatchMask::MODIFY)
+        .context("watching log file")?;
+    let watches = Watches {
+        events,
+        file_watch,
+        dir_watch,
+    };
+    tail_file(tag, path, Start::Beginning, raw, tx, watches).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, OpenOptions};
+    use svmgr::log::LogEntry;
+
+    /// empty directory for a test's log files
+    fn test_dir(name: &str) -> Utf8PathBuf {
+        let dir = Utf8PathBuf::from_path_buf(env::temp_dir())
+            .unwrap()
+            .join(format!("logread-test-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn append(path: &Path, payloads: &[&str]) {
+        let mut bytes = Vec::new();
+        for payload in payloads {
+            LogEntry::new(payload.as_bytes()).serialize(&TimestampFormat::default(), &mut bytes);
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap();
+        file.write_all(&bytes).unwrap();
+    }
+
+    fn inode(path: &Path) -> u64 {
+        fs::metadata(path).unwrap().ino()
+    }
+
+    fn queue() -> (
+        queue::Receiver<TaggedLogEntry>,
+        queue::Sender<TaggedLogEntry>,
+    ) {
+        let mut rx = queue::Receiver::new(NonZeroUsize::new(100).unwrap(), Overflow::Block);
+        let tx = rx.sender();
+        (rx, tx)
+    }
+
+    /// everything queued on `tx`
+    async fn received(
+        mut rx: queue::Receiver<TaggedLogEntry>,
+        tx: queue::Sender<TaggedLogEntry>,
+    ) -> Vec<(String, Cursor)> {
+        drop(tx);
+        let mut entries = Vec::new();
+        while let Some((tagged, _)) = rx.recv().await {
+            let Payload::Decoded(entry) = tagged.entry else {
+                panic!("raw entry")
+            };
+            let payload = String::from_utf8(entry.as_slice().to_vec()).unwrap();
+            entries.push((payload, tagged.cursor.unwrap()));
+        }
+        entries
+    }
+
+    fn payloads(entries: &[(String, Cursor)]) -> Vec<&str> {
+        entries
+            .iter()
+            .map(|(payload, _)| payload.as_str())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn swapped_inode_is_read_once() {
+        let dir = test_dir("swap");
+        let current = dir.join("current");
+        let tag = Tag::new("swap").unwrap();
+        append(&current, &["a", "b"]);
+
+        let (rx, tx) = queue();
+        let mut followed = FollowedFile::open(tag, &current, Start::Beginning, false, &tx)
+            .await
+            .unwrap();
+        assert_eq!(payloads(&received(rx, tx).await), ["a", "b"]);
+
+        // rotated by renaming, with an entry written to the old file just before
+        append(&current, &["c"]);
+        let old_inode = inode(&current);
+        fs::rename(&current, dir.join("@1")).unwrap();
+        append(&current, &["d"]);
+        let (rx, tx) = queue();
+        assert!(followed.update(tag, &current, false, &tx).await.unwrap());
+        let entries = received(rx, tx).await;
+        assert_eq!(payloads(&entries), ["c", "d"]);
+        assert_eq!(entries[0].1.inode, old_inode);
+        assert_eq!(entries[1].1.inode, inode(&current));
+
+        // replaced by renaming another file onto it
+        append(&current, &["e"]);
+        let new = dir.join("new");
+        append(&new, &["f", "g"]);
+        fs::rename(&new, &current).unwrap();
+        let (rx, tx) = queue();
+        assert!(followed.update(tag, &current, false, &tx).await.unwrap());
+        assert_eq!(payloads(&received(rx, tx).await), ["e", "f", "g"]);
+
+        // nothing changed
+        let (rx, tx) = queue();
+        assert!(!followed.update(tag, &current, false, &tx).await.unwrap());
+        assert!(received(rx, tx).await.is_empty());
+
+        // moved away, the new file isn't there yet
+        append(&current, &["h"]);
+        fs::rename(&current, dir.join("@2")).unwrap();
+        let (rx, tx) = queue();
+        assert!(!followed.update(tag, &current, false, &tx).await.unwrap());
+        assert_eq!(payloads(&received(rx, tx).await), ["h"]);
+        append(&current, &["i"]);
+        let (rx, tx) = queue();
+        assert!(followed.update(tag, &current, false, &tx).await.unwrap());
+        assert_eq!(payloads(&received(rx, tx).await), ["i"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// follows `current` from `cursor` and returns what was read right away
+    async fn resumed(tag: Tag, current: &Path, cursor: Cursor) -> Vec<String> {
+        let (rx, tx) = queue();
+        FollowedFile::open(tag, current, Start::At(cursor), false, &tx)
+            .await
+            .unwrap();
+        let entries = received(rx, tx).await;
+        payloads(&entries).into_iter().map(str::to_owned).collect()
+    }
+
+    #[tokio::test]
+    async fn resume_picks_up_rotated_file() {
+        let dir = test_dir("resume");
+        let current = dir.join("current");
+        let tag = Tag::new("resume").unwrap();
+        append(&current, &["a"]);
+        let cursor = Cursor {
+            inode: inode(&current),
+            offset: fs::metadata(&current).unwrap().len(),
+        };
+        append(&current, &["b"]);
+
+        // same file
+        assert_eq!(resumed(tag, &current, cursor).await, ["b"]);
+        // shorter than the position
+        let past_end = Cursor {
+            offset: 1 << 20,
+            ..cursor
+        };
+        assert_eq!(resumed(tag, &current, past_end).await, ["a", "b"]);
+
+        // rotated, the rest of the old file comes first
+        fs::rename(&current, dir.join("@1")).unwrap();
+        append(&current, &["c"]);
+        assert_eq!(resumed(tag, &current, cursor).await, ["b", "c"]);
+
+        // rotated away completely
+        fs::remove_file(dir.join("@1")).unwrap();
+        assert_eq!(resumed(tag, &current, cursor).await, ["c"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,234 @@
+//! Per-log entry queues merged by the printer
+//!
+//! Every log gets its own bounded queue, so a log producing lots of entries can't stall reading the
+//! others while the printer catches up with it. When a queue is full its reader either waits for
+//! space, applying backpressure all the way to the file reads, or drops the oldest queued entry.
+
+use clap::ArgEnum;
+use std::collections::VecDeque;
+use std::fmt;
+use std::mem;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use tokio::runtime::Handle;
+use tokio::sync::Notify;
+
+/// What a reader does when its queue is full
+#[derive(ArgEnum, Clone, Copy, Debug)]
+pub enum Overflow {
+    /// Wait until the printer catches up, nothing is lost
+    Block,
+    /// Drop the oldest queued entry, the reader never falls behind the log
+    DropOldest,
+}
+
+struct Queue<T> {
+    state: Mutex<State<T>>,
+    capacity: NonZeroUsize,
+    overflow: Overflow,
+    /// wakes the reader waiting for space
+    space: Notify,
+    /// wakes the printer, shared by all queues
+    ready: Arc<Notify>,
+}
+
+struct State<T> {
+    entries: VecDeque<T>,
+    /// entries dropped since the printer last took one
+    dropped: u64,
+    /// the reader is gone
+    closed: bool,
+    /// the printer is gone
+    disconnected: bool,
+}
+
+/// The printer is gone, nothing will take entries anymore
+#[derive(Debug)]
+pub struct Disconnected;
+
+impl fmt::Display for Disconnected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the printer is gone")
+    }
+}
+
+impl std::error::Error for Disconnected {}
+
+/// Reader end of one queue, closes the queue when dropped
+pub struct Sender<T> {
+    queue: Arc<Queue<T>>,
+}
+
+impl<T> Sender<T> {
+    /// queues an entry, fails once the printer is gone so the reader can stop
+    pub async fn send(&self, entry: T) -> Result<(), Disconnected> {
+        let mut entry = Some(entry);
+        while let Some(pending) = entry.take() {
+            {
+                let mut state = self.queue.state.lock().unwrap();
+                if state.disconnected {
+                    return Err(Disconnected);
+                }
+                if state.entries.len() < self.queue.capacity.get() {
+                    state.entries.push_back(pending);
+                } else if let Overflow::DropOldest = self.queue.overflow {
+                    state.entries.pop_front();
+                    state.dropped += 1;
+                    state.entries.push_back(pending);
+                } else {
+                    entry = Some(pending);
+                }
+            }
+            if entry.is_some() {
+                self.queue.space.notified().await;
+            }
+        }
+        self.queue.ready.notify_one();
+        Ok(())
+    }
+
+    /// [`Sender::send`] for readers running in a blocking task
+    ///
+    /// the runtime waits for blocking tasks when it shuts down, so these have to stop on the error
+    pub fn blocking_send(&self, entry: T) -> Result<(), Disconnected> {
+        Handle::current().block_on(self.send(entry))
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.queue.state.lock().unwrap().closed = true;
+        self.queue.ready.notify_one();
+    }
+}
+
+/// Printer end of all the queues
+pub struct Receiver<T> {
+    queues: Vec<Arc<Queue<T>>>,
+    capacity: NonZeroUsize,
+    overflow: Overflow,
+    ready: Arc<Notify>,
+    /// queue to look at first next time, rotates so no log gets starved
+    next: usize,
+}
+
+impl<T> Receiver<T> {
+    pub fn new(capacity: NonZeroUsize, overflow: Overflow) -> Self {
+        Receiver {
+            queues: Vec::new(),
+            capacity,
+            overflow,
+            ready: Arc::new(Notify::new()),
+            next: 0,
+        }
+    }
+
+    /// creates a queue for another log
+    pub fn sender(&mut self) -> Sender<T> {
+        let queue = Arc::new(Queue {
+            state: Mutex::new(State {
+                entries: VecDeque::with_capacity(self.capacity.get()),
+                dropped: 0,
+                closed: false,
+                disconnected: false,
+            }),
+            capacity: self.capacity,
+            overflow: self.overflow,
+            space: Notify::new(),
+            ready: Arc::clone(&self.ready),
+        });
+        self.queues.push(Arc::clone(&queue));
+        Sender { queue }
+    }
+
+    /// takes the next entry from any of the queues together with the number of entries dropped
+    /// from its queue before it, returns `None` once all queues are empty and closed
+    pub async fn recv(&mut self) -> Option<(T, u64)> {
+        loop {
+            match self.take() {
+                Ok(entry) => return Some(entry),
+                Err(true) => return None,
+                Err(false) => self.ready.notified().await,
+            }
+        }
+    }
+
+    /// like [`Receiver::recv`] but doesn't wait, `None` when no queue has an entry right now
+    pub fn try_recv(&mut self) -> Option<(T, u64)> {
+        self.take().ok()
+    }
+
+    /// the next entry, or whether all queues are closed when there is none
+    fn take(&mut self) -> Result<(T, u64), bool> {
+        let mut all_closed = true;
+        for i in 0..self.queues.len() {
+            let index = (self.next + i) % self.queues.len();
+            let queue = &self.queues[index];
+            let mut state = queue.state.lock().unwrap();
+            if let Some(entry) = state.entries.pop_front() {
+                let dropped = mem::take(&mut state.dropped);
+                drop(state);
+                queue.space.notify_one();
+                self.next = index + 1;
+                return Ok((entry, dropped));
+            }
+            all_closed &= state.closed;
+        }
+        Err(all_closed)
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        for queue in &self.queues {
+            queue.state.lock().unwrap().disconnected = true;
+            // wake readers waiting for space
+            queue.space.notify_one();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::task;
+
+    #[tokio::test]
+    async fn blocked_send_fails_once_the_receiver_is_gone() {
+        let mut rx = Receiver::new(NonZeroUsize::new(1).unwrap(), Overflow::Block);
+        let tx = rx.sender();
+        // fills the queue, the blocking task below has to wait for space
+        tx.send(1).await.unwrap();
+        let blocked = task::spawn_blocking(move || tx.blocking_send(2));
+        task::yield_now().await;
+        drop(rx);
+        assert!(blocked.await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn receiver_drains_closed_queues() {
+        let mut rx = Receiver::new(NonZeroUsize::new(2).unwrap(), Overflow::DropOldest);
+        let tx = rx.sender();
+        for entry in 1..=3 {
+            tx.send(entry).await.unwrap();
+        }
+        drop(tx);
+        assert_eq!(rx.recv().await, Some((2, 1)));
+        assert_eq!(rx.recv().await, Some((3, 0)));
+        assert_eq!(rx.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn try_recv_takes_what_is_queued() {
+        let mut rx = Receiver::new(NonZeroUsize::new(2).unwrap(), Overflow::Block);
+        let first = rx.sender();
+        let second = rx.sender();
+        first.send(1).await.unwrap();
+        second.send(2).await.unwrap();
+        // the queues are still open, only what's queued is taken
+        let mut taken = [rx.try_recv(), rx.try_recv()];
+        taken.sort();
+        assert_eq!(taken, [Some((1, 0)), Some((2, 0))]);
+        assert_eq!(rx.try_recv(), None);
+    }
+}
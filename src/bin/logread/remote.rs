@@ -0,0 +1,215 @@
+//! Following logs of another host over TCP
+//!
+//! `logread --serve` tails logs for `logread --remote` clients. The protocol is line based until
+//! the log stream starts:
+//!
+//! - the client sends the log it wants as `{tag}\n` or `{user}/{tag}\n`
+//! - the server answers `ok\n` and streams the log's new entries as `logread --raw` prints them,
+//!   or answers `error: {message}\n` and closes the connection
+//!
+//! The stream never ends on its own, the client closes the connection when it's done. There is no
+//! authentication or encryption, the server should listen on localhost and be reached through an
+//! SSH tunnel.
+
+use crate::{
+    next_payload, parse_log, print_entry, queue, tail_log, Follow, Start, Tag, TaggedLogEntry,
+};
+use anyhow::{bail, Context, Result};
+use camino::Utf8Path as Path;
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::time::Duration;
+use svmgr::log::{LogReader, ReadEntryError};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, Mutex};
+use tokio::{task, time};
+
+/// longest request or response line, anything longer is a protocol error
+const MAX_LINE_LEN: u64 = 1024;
+/// entries queued per connection before the tailing waits for the client
+const QUEUE_LEN: usize = 64;
+const RECONNECT_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_MAX: Duration = Duration::from_secs(60);
+
+/// accepts clients on `addr` until a signal arrives
+pub async fn serve(
+    addr: &str,
+    base_path: &'static Path,
+    follow: Follow,
+    signals: &mut mpsc::UnboundedReceiver<libc::c_int>,
+) -> Result<()> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("listen on `{addr}`"))?;
+    // tags leak their names, every log gets only one
+    let tags: &'static Mutex<HashMap<String, Tag>> = Box::leak(Box::default());
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (stream, peer) = match accepted {
+                    Ok(accepted) => accepted,
+                    Err(err) => {
+                        eprintln!("accept connection: {err}");
+                        continue;
+                    }
+                };
+                task::spawn(async move {
+                    if let Err(err) = serve_client(stream, base_path, follow, tags).await {
+                        eprintln!("[{peer}] {err:#}");
+                    }
+                });
+            }
+            _ = signals.recv() => return Ok(()),
+        }
+    }
+}
+
+async fn serve_client(
+    stream: TcpStream,
+    base_path: &Path,
+    follow: Follow,
+    tags: &Mutex<HashMap<String, Tag>>,
+) -> Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut read = BufReader::new(read);
+    let request = read_line(&mut read).await.context("read request")?;
+    let tag = match requested_tag(&request, base_path, tags).await {
+        Ok(tag) => tag,
+        Err(message) => {
+            let response = format!("error: {message}\n");
+            write.write_all(response.as_bytes()).await?;
+            return Ok(());
+        }
+    };
+    write.write_all(b"ok\n").await.context("write response")?;
+
+    let mut rx = queue::Receiver::new(
+        NonZeroUsize::new(QUEUE_LEN).unwrap(),
+        queue::Overflow::Block,
+    );
+    let tx = rx.sender();
+    let path = tag.path(base_path);
+    let tail = task::spawn(async move { tail_log(tag, &path, Start::End, follow, &tx).await });
+
+    let mut raw_format = None;
+    let mut out = Vec::new();
+    let result = loop {
+        tokio::select! {
+            entry = rx.recv() => {
+                let Some((entry, _)) = entry else {
+                    break Ok(());
+                };
+                out.clear();
+                print_entry(&mut out, &entry, &mut raw_format, false)?;
+                if let Err(err) = write.write_all(&out).await {
+                    break Err(err).context("write entries");
+                }
+            }
+            // clients don't send anything after the request, this only returns once they're gone
+            _ = read.read_u8() => break Ok(()),
+        }
+    };
+    tail.abort();
+    result
+}
+
+/// the tag of an existing log named by `request`, or the message for the client
+async fn requested_tag(
+    request: &str,
+    base_path: &Path,
+    tags: &Mutex<HashMap<String, Tag>>,
+) -> Result<Tag, String> {
+    let mut tags = tags.lock().await;
+    if let Some(tag) = tags.get(request) {
+        return Ok(*tag);
+    }
+    // validated and checked before `Tag::new` leaks the names
+    parse_log(request).map_err(|err| err.to_string())?;
+    if !base_path.join(request).exists() {
+        return Err(format!("log `{request}` does not exist"));
+    }
+    let tag = Tag::new(request).map_err(|err| err.to_string())?;
+    tags.insert(request.to_owned(), tag);
+    Ok(tag)
+}
+
+/// follows `tag` on the server at `addr`, reconnecting until the server refuses the request
+pub async fn follow(addr: &str, tag: Tag, raw: bool, tx: &queue::Sender<TaggedLogEntry>) {
+    let mut delay = RECONNECT_MIN;
+    loop {
+        match follow_connection(addr, tag, raw, tx, &mut delay).await {
+            Ok(Closed::Refused(message)) => {
+                eprintln!("[{tag}] `{addr}` refused: {message}");
+                return;
+            }
+            Ok(Closed::Eof) => eprintln!("[{tag}] `{addr}` closed the connection"),
+            Err(err) if err.is::<queue::Disconnected>() => return,
+            Err(err) => eprintln!("[{tag}] `{addr}`: {err:#}"),
+        }
+        eprintln!("[{tag}] reconnecting in {delay:?}, entries written meanwhile are missed");
+        time::sleep(delay).await;
+        delay = (delay * 2).min(RECONNECT_MAX);
+    }
+}
+
+/// How a connection to the server ended
+enum Closed {
+    Refused(String),
+    Eof,
+}
+
+/// requests the log and reads its entries until the connection ends, resets `delay` once the
+/// server accepted the request
+async fn follow_connection(
+    addr: &str,
+    tag: Tag,
+    raw: bool,
+    tx: &queue::Sender<TaggedLogEntry>,
+    delay: &mut Duration,
+) -> Result<Closed> {
+    let stream = TcpStream::connect(addr).await.context("connect")?;
+    let (read, mut write) = stream.into_split();
+    write
+        .write_all(format!("{tag}\n").as_bytes())
+        .await
+        .context("write request")?;
+    let mut read = BufReader::new(read);
+    let response = read_line(&mut read).await.context("read response")?;
+    match response.strip_prefix("error: ") {
+        Some(message) => return Ok(Closed::Refused(message.to_owned())),
+        None if response != "ok" => bail!("unexpected response `{response}`"),
+        None => {}
+    }
+    *delay = RECONNECT_MIN;
+
+    let mut log_reader = LogReader::new();
+    let mut format = None;
+    loop {
+        match next_payload(&mut log_reader, &mut read, raw, &mut format).await {
+            Ok(entry) => {
+                let tagged = TaggedLogEntry {
+                    tag,
+                    entry,
+                    cursor: None,
+                };
+                tx.send(tagged).await?;
+            }
+            Err(ReadEntryError::DeserializeError(err)) => {
+                eprintln!("[{tag}] skipping corrupt entry: {err}");
+            }
+            Err(_) if log_reader.incomplete => return Ok(Closed::Eof),
+            Err(err) => return Err(err).context("read log entry"),
+        }
+    }
+}
+
+/// reads a line without its newline, failing if it's too long or the connection ends first
+async fn read_line<R: AsyncBufReadExt + Unpin>(reader: &mut R) -> Result<String> {
+    let mut line = String::new();
+    reader.take(MAX_LINE_LEN).read_line(&mut line).await?;
+    match line.strip_suffix('\n') {
+        Some(line) => Ok(line.to_owned()),
+        None => bail!("line is too long or incomplete"),
+    }
+}
@@ -0,0 +1,69 @@
+//! Read throughput statistics printed with `--stats`
+//!
+//! The readers add what their [`LogReader`](svmgr::log::LogReader) counted to a per-log total, the
+//! printer periodically samples the totals into rolling windows. Nothing is recorded unless the
+//! statistics are enabled.
+
+use crate::Tag;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use svmgr::log::{ReadCounters, StatsWindow};
+
+/// rates are averaged over this long
+const WINDOW: Duration = Duration::from_secs(60);
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static TOTALS: Mutex<BTreeMap<Tag, ReadCounters>> = Mutex::new(BTreeMap::new());
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// adds the reader's `counters` since the `last` recorded ones to the log's total
+pub fn record(tag: Tag, counters: ReadCounters, last: &mut ReadCounters) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let delta = counters.since(last);
+    *last = counters;
+    TOTALS.lock().unwrap().entry(tag).or_default().add(&delta);
+}
+
+#[derive(Default)]
+pub struct Printer {
+    windows: BTreeMap<Tag, StatsWindow>,
+}
+
+impl Printer {
+    /// samples the totals and prints the rates of every log to stderr
+    pub fn print(&mut self) {
+        let now = Instant::now();
+        let totals = TOTALS.lock().unwrap().clone();
+        for (tag, counters) in totals {
+            let window = self
+                .windows
+                .entry(tag)
+                .or_insert_with(|| StatsWindow::new(WINDOW));
+            let stats = window.sample(now, counters);
+            if stats.elapsed.is_zero() {
+                // first sample of this log, no rates yet
+                continue;
+            }
+            eprint!(
+                "[{tag}] {:.1} entries/s, {:.1} KiB/s over the last {:.1}s",
+                stats.entries_per_sec,
+                stats.bytes_per_sec / 1024.0,
+                stats.elapsed.as_secs_f64(),
+            );
+            if stats.garbage_bytes > 0 {
+                eprint!(
+                    ", skipped {} bytes ({} truncated entries)",
+                    stats.garbage_bytes, stats.false_starts,
+                );
+            }
+            eprintln!();
+        }
+    }
+}
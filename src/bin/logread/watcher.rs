@@ -0,0 +1,280 @@
+//! One inotify instance for everything `logread` follows
+//!
+//! Instances are limited per user by `fs.inotify.max_user_instances`, so the followed logs and the
+//! discovery of new logs share one and its events are dispatched by watch descriptor. inotify
+//! returns the same descriptor for every watch of an inode, a watch is only removed once the last
+//! [`Watch`] on it is dropped.
+
+use inotify::{EventMask, EventOwned, EventStream, Inotify, WatchDescriptor, WatchMask};
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{mpsc, Notify};
+use tokio::task;
+use tokio_stream::StreamExt;
+
+/// events queued for every [`Events`], a subscriber that falls further behind gets
+/// [`Event::Missed`] instead
+const EVENTS_LEN: usize = 32;
+/// room for plenty of events with the longest file names
+const BUFFER_LEN: usize = 16 * 1024;
+
+/// The shared inotify instance
+pub struct Watcher {
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    inotify: Inotify,
+    subscribers: HashMap<WatchDescriptor, Vec<Subscriber>>,
+    next_id: u64,
+    /// why the events stopped, nothing is watched anymore after that
+    error: Option<(io::ErrorKind, String)>,
+}
+
+struct Subscriber {
+    id: u64,
+    mask: EventMask,
+    tx: mpsc::Sender<EventOwned>,
+    missed: Arc<Notify>,
+}
+
+impl Watcher {
+    /// creates the instance and starts dispatching its events, has to run inside the runtime
+    pub fn init() -> io::Result<&'static Watcher> {
+        let mut inotify = Inotify::init()?;
+        let stream = inotify.event_stream(vec![0; BUFFER_LEN].into_boxed_slice())?;
+        let watcher: &'static Watcher = Box::leak(Box::new(Watcher {
+            inner: Mutex::new(Inner {
+                inotify,
+                subscribers: HashMap::new(),
+                next_id: 0,
+                error: None,
+            }),
+        }));
+        task::spawn(watcher.dispatch(stream));
+        Ok(watcher)
+    }
+
+    /// a new subscriber, receives the events of the watches added through it
+    pub fn events(&'static self) -> Events {
+        let (tx, rx) = mpsc::channel(EVENTS_LEN);
+        Events {
+            watcher: self,
+            tx,
+            rx,
+            missed: Arc::default(),
+        }
+    }
+
+    async fn dispatch(&self, mut stream: EventStream<Box<[u8]>>) {
+        let err = loop {
+            let event = match stream.next().await {
+                Some(Ok(event)) => event,
+                Some(Err(err)) => break err,
+                None => break io::Error::new(io::ErrorKind::UnexpectedEof, "inotify closed"),
+            };
+            let mut inner = self.inner.lock().unwrap();
+            if event.mask.contains(EventMask::Q_OVERFLOW) {
+                // the kernel dropped events, they could have been anyone's
+                for subscriber in inner.subscribers.values().flatten() {
+                    subscriber.missed.notify_one();
+                }
+                continue;
+            }
+            // the kernel removed the watch, every subscriber of it has to know
+            let ignored = event.mask.contains(EventMask::IGNORED);
+            let subscribers = inner.subscribers.get(&event.wd).into_iter().flatten();
+            for subscriber in
+                subscribers.filter(|subscriber| ignored || subscriber.mask.intersects(event.mask))
+            {
+                if subscriber.tx.try_send(event.clone()).is_err() {
+                    subscriber.missed.notify_one();
+                }
+            }
+            if ignored {
+                inner.subscribers.remove(&event.wd);
+            }
+        };
+
+        let mut inner = self.inner.lock().unwrap();
+        inner.error = Some((err.kind(), format!("reading inotify events: {err}")));
+        // they find the error when they come to check what they missed
+        for subscriber in inner.subscribers.values().flatten() {
+            subscriber.missed.notify_one();
+        }
+    }
+}
+
+impl Inner {
+    fn check_error(&self) -> io::Result<()> {
+        match &self.error {
+            Some((kind, message)) => Err(io::Error::new(*kind, message.clone())),
+            None => Ok(()),
+        }
+    }
+}
+
+/// An event of a subscriber's watches, `IN_IGNORED` arrives without asking for it
+pub enum Event {
+    Watched(EventOwned),
+    /// events were dropped, everything watched has to be checked again
+    Missed,
+}
+
+/// A subscriber of the [`Watcher`], collects the events of its watches
+pub struct Events {
+    watcher: &'static Watcher,
+    tx: mpsc::Sender<EventOwned>,
+    rx: mpsc::Receiver<EventOwned>,
+    missed: Arc<Notify>,
+}
+
+impl Events {
+    /// watches `path` for `mask`, more events of the inode may arrive if others watch it as well
+    pub fn add(&self, path: impl AsRef<Path>, mask: WatchMask) -> io::Result<Watch> {
+        let mut inner = self.watcher.inner.lock().unwrap();
+        inner.check_error()?;
+        // others' watches of the inode have to keep their events
+        let wd = inner.inotify.add_watch(path, mask | WatchMask::MASK_ADD)?;
+        let id = inner.next_id;
+        inner.next_id += 1;
+        inner
+            .subscribers
+            .entry(wd.clone())
+            .or_default()
+            .push(Subscriber {
+                id,
+                mask: EventMask::from_bits_truncate(mask.bits()),
+                tx: self.tx.clone(),
+                missed: self.missed.clone(),
+            });
+        Ok(Watch {
+            watcher: self.watcher,
+            wd,
+            id,
+        })
+    }
+
+    /// waits for the next event, fails once the watcher stopped
+    pub async fn next(&mut self) -> io::Result<Event> {
+        tokio::select! {
+            biased;
+            // never closed, we hold a sender
+            Some(event) = self.rx.recv() => Ok(Event::Watched(event)),
+            () = self.missed.notified() => {
+                self.watcher.inner.lock().unwrap().check_error()?;
+                Ok(Event::Missed)
+            }
+        }
+    }
+}
+
+/// A watch added by [`Events::add`], removed when dropped
+pub struct Watch {
+    watcher: &'static Watcher,
+    wd: WatchDescriptor,
+    id: u64,
+}
+
+impl Watch {
+    pub fn wd(&self) -> &WatchDescriptor {
+        &self.wd
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        let mut inner = self.watcher.inner.lock().unwrap();
+        let Some(subscribers) = inner.subscribers.get_mut(&self.wd) else {
+            return;
+        };
+        subscribers.retain(|subscriber| subscriber.id != self.id);
+        if subscribers.is_empty() {
+            inner.subscribers.remove(&self.wd);
+            // the kernel removes watches of deleted files on its own
+            let _ = inner.inotify.rm_watch(self.wd.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::Duration;
+    use tokio::time;
+
+    /// the names of the next events, nothing more may be queued
+    async fn names(events: &mut Events, count: usize) -> Vec<String> {
+        let mut names = Vec::new();
+        for _ in 0..count {
+            let event = time::timeout(Duration::from_secs(5), events.next())
+                .await
+                .expect("timed out waiting for an event")
+                .unwrap();
+            match event {
+                Event::Watched(event) => names.push(event.name.unwrap().into_string().unwrap()),
+                Event::Missed => panic!("missed events"),
+            }
+        }
+        assert!(events.rx.try_recv().is_err());
+        names
+    }
+
+    #[tokio::test]
+    async fn watches_of_one_directory_are_shared() {
+        let dir = std::env::temp_dir().join(format!("logread-watcher-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let watcher = Watcher::init().unwrap();
+        let mut first = watcher.events();
+        let mut second = watcher.events();
+        let first_watch = first.add(&dir, WatchMask::CREATE).unwrap();
+        let second_watch = second.add(&dir, WatchMask::CREATE).unwrap();
+        assert_eq!(first_watch.wd(), second_watch.wd());
+
+        fs::write(dir.join("a"), "").unwrap();
+        assert_eq!(names(&mut first, 1).await, ["a"]);
+        assert_eq!(names(&mut second, 1).await, ["a"]);
+
+        // the other subscriber keeps the watch
+        drop(first_watch);
+        fs::write(dir.join("b"), "").unwrap();
+        assert_eq!(names(&mut second, 1).await, ["b"]);
+        assert!(first.rx.try_recv().is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn removed_watches_are_reported_and_forgotten() {
+        let dir = std::env::temp_dir().join(format!("logread-ignored-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let watcher = Watcher::init().unwrap();
+        let mut events = watcher.events();
+        let watch = events.add(&dir, WatchMask::CREATE).unwrap();
+        fs::remove_dir(&dir).unwrap();
+        let event = time::timeout(Duration::from_secs(5), events.next())
+            .await
+            .expect("timed out waiting for an event")
+            .unwrap();
+        match event {
+            Event::Watched(event) => {
+                assert_eq!(event.wd, *watch.wd());
+                assert!(event.mask.contains(EventMask::IGNORED));
+            }
+            Event::Missed => panic!("missed events"),
+        }
+        assert!(!watcher
+            .inner
+            .lock()
+            .unwrap()
+            .subscribers
+            .contains_key(watch.wd()));
+    }
+}
@@ -1,23 +1,78 @@
 //! Reads `stdin` and logs it into a file in the `svmgr` log format.
 //!
 //! For system mode logs are written into `/var/log/sv/{tag}/current`, for user mode logs are
-//! written into `/var/log/sv/{user}/{tag}`.
+//! written into `/var/log/sv/{user}/{tag}`. `SVMGR_LOG_DIR` replaces `/var/log/sv`.
+//!
+//! With `--raw-in` stdin is a stream of serialized entries like `logread --raw` prints, they are
+//! appended with their original timestamps.
+//!
+//! On `SIGHUP` the log file is reopened, after [`svmgr::log::rotate`] moved it aside this starts a
+//! new `current`.
 
-use anyhow::{Context, Result};
-use camino::Utf8Path as Path;
+use anyhow::{bail, Context, Result};
+use camino::{Utf8Path as Path, Utf8PathBuf};
+use chrono::NaiveDateTime;
 use clap::Parser;
-use std::io::{Read, Write};
-use std::{fs, io};
-use svmgr::log::LogEntry;
+use std::io::{ErrorKind, Read, Write};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd};
+use std::time::{Duration, Instant};
+use std::{fmt, fs, io};
+use svmgr::cli::parse_duration;
+use svmgr::config::UnitName;
+use svmgr::log::{self, LogEntry, LogReader, ReadEntryError, TimestampFormat, MAX_HEADER_LEN};
+use svmgr::signal::{self, Received};
 
 #[derive(Parser, Debug)]
 struct Args {
     /// If present `log` starts in user mode for the given user
+    ///
+    /// The user name is a directory in the log directory and has to be a valid unit name as well.
+    #[clap(long)]
+    user: Option<UnitName>,
+
+    /// Timestamp format for a new log file, `chrono` strftime syntax
+    ///
+    /// Existing files keep the format recorded in their header.
+    #[clap(long)]
+    timestamp_format: Option<String>,
+
+    /// Log this text as an entry before reading stdin
+    ///
+    /// Used by the manager to record events such as a restart of the logger.
+    #[clap(long)]
+    marker: Option<String>,
+
+    /// Only end entries at newlines so every entry holds whole lines
+    ///
+    /// A partial line is logged anyway after `--line-timeout` without more input or once it
+    /// reaches the maximum entry size. By default entries are whatever chunks stdin delivers.
+    #[clap(long)]
+    line_mode: bool,
+
+    /// How long `--line-mode` holds back a partial line, e.g. `500ms`
+    #[clap(long, default_value = "1s", parse(try_from_str = parse_duration))]
+    line_timeout: Duration,
+
+    /// Read serialized entries from stdin and keep their timestamps, e.g. from `logread --raw`
+    ///
+    /// The input may contain header frames changing the timestamp format, entries are written in
+    /// the format of the log file.
+    #[clap(long, conflicts_with = "line-mode")]
+    raw_in: bool,
+
+    /// Skip corrupt entries in the `--raw-in` input instead of failing
+    #[clap(long, requires = "raw-in")]
+    skip_corrupt: bool,
+
+    /// Never write a timestamp older than the previous one
+    ///
+    /// When the clock steps back, entries get the latest timestamp until the clock catches up and
+    /// a marker records the jump. The latest timestamp is picked up from the end of the log file.
     #[clap(long)]
-    user: Option<String>,
+    monotonic: bool,
 
     /// Log tag, usually the service name
-    tag: String,
+    tag: UnitName,
 }
 
 /// maximum payload size for one log entry
@@ -26,39 +81,406 @@ const LOGENTRY_LIMIT: usize = 4096;
 fn main() -> Result<()> {
     let args = Args::parse();
 
-    let base_path = Path::new("/var/log/sv");
-    let log_dir_path = match &args.user {
-        Some(user) => base_path.join(user).join(&args.tag),
-        None => base_path.join(&args.tag),
-    };
+    // before opening anything, the default action of SIGHUP would kill us
+    let reopen = signal::interrupt(signal::SIGHUP).context("install SIGHUP handler")?;
 
+    let log_dir_path = log::log_dir(args.user.as_ref(), &args.tag);
     fs::create_dir_all(&log_dir_path)
         .with_context(|| format!("create log directory: `{log_dir_path}`"))?;
 
-    let log_file_path = log_dir_path.join("current");
-    let mut log_file = fs::OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(&log_file_path)
-        .with_context(|| format!("open log file for appending: `{log_file_path}`"))?;
+    let requested_format = match &args.timestamp_format {
+        Some(format) => Some(TimestampFormat::new(format)?),
+        None => None,
+    };
+    let mut log = Log::open(
+        log_dir_path.join("current"),
+        requested_format,
+        args.monotonic,
+        reopen,
+    )?;
 
     let stdin = io::stdin();
-    let mut stdin = stdin.lock();
 
-    let mut in_buffer = Box::new([0u8; LOGENTRY_LIMIT]);
-    let mut out_buffer = Vec::new();
+    if let Some(marker) = &args.marker {
+        let marker = &marker.as_bytes()[..marker.len().min(LOGENTRY_LIMIT)];
+        log.write(&LogEntry::new(marker))?;
+    }
 
+    if args.raw_in {
+        return log_frames(RetryInterrupted(stdin.lock()), args.skip_corrupt, &mut log);
+    }
+
+    if args.line_mode {
+        // unbuffered, data in the buffer of `Stdin` would be invisible to `poll`
+        let input = stdin
+            .as_fd()
+            .try_clone_to_owned()
+            .context("duplicate stdin")?;
+        return log_lines(fs::File::from(input), args.line_timeout, &mut log);
+    }
+
+    let mut stdin = stdin.lock();
+    let mut in_buffer = Box::new([0u8; LOGENTRY_LIMIT]);
     loop {
         match stdin.read(&mut *in_buffer) {
             Ok(0) => break Ok(()), // EOF
+            Err(err) if err.kind() == ErrorKind::Interrupted => log.reopen_if_requested()?,
             Err(err) => break Err(err).context("read stdin"),
-            Ok(n) => {
-                let log_entry = LogEntry::new(&in_buffer[..n]);
-                out_buffer.clear();
-                log_entry.serialize(&mut out_buffer);
-                log_file.write_all(&out_buffer).context("write log entry")?;
-                log_file.flush().context("flush log file")?;
+            Ok(n) => log.write(&LogEntry::new(&in_buffer[..n]))?,
+        }
+    }
+}
+
+/// The log file entries are appended to
+struct Log {
+    path: Utf8PathBuf,
+    file: fs::File,
+    format: TimestampFormat,
+    /// set with `--monotonic`
+    monotonic: Option<Monotonic>,
+    /// `SIGHUP` arrived, the file has to be reopened
+    reopen: Received,
+    out_buffer: Vec<u8>,
+}
+
+impl Log {
+    fn open(
+        path: Utf8PathBuf,
+        requested_format: Option<TimestampFormat>,
+        monotonic: bool,
+        reopen: Received,
+    ) -> Result<Log> {
+        let (mut file, format) = open_log_file(&path, requested_format)?;
+        let monotonic = if monotonic {
+            let last = log::last_timestamp(&mut file, &format)
+                .with_context(|| format!("read last entry: `{path}`"))?;
+            Some(Monotonic {
+                last,
+                behind: false,
+            })
+        } else {
+            None
+        };
+        Ok(Log {
+            path,
+            file,
+            format,
+            monotonic,
+            reopen,
+            out_buffer: Vec::new(),
+        })
+    }
+
+    /// opens the file at our path again if `SIGHUP` arrived, keeping the timestamp format
+    fn reopen_if_requested(&mut self) -> Result<()> {
+        if self.reopen.take() {
+            let (file, format) = open_log_file(&self.path, Some(self.format.clone()))?;
+            self.file = file;
+            self.format = format;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, log_entry: &LogEntry) -> Result<()> {
+        self.reopen_if_requested()?;
+        let Some(monotonic) = &mut self.monotonic else {
+            return self.append(log_entry);
+        };
+        let (timestamp, jump) = monotonic.stamp(log_entry.timestamp());
+        if let Some(jump) = jump {
+            let marker = format!(
+                "svmgr: timestamps went back by {:?}, holding them at the latest one until they \
+                 catch up",
+                jump.to_std().unwrap_or_default(),
+            );
+            self.append(&LogEntry::new(marker.as_bytes()).with_timestamp(timestamp))?;
+        }
+        self.append(&log_entry.with_timestamp(timestamp))
+    }
+
+    fn append(&mut self, log_entry: &LogEntry) -> Result<()> {
+        self.out_buffer.clear();
+        log_entry.serialize(&self.format, &mut self.out_buffer);
+        self.file
+            .write_all(&self.out_buffer)
+            .context("write log entry")?;
+        self.file.flush().context("flush log file")
+    }
+}
+
+/// Clamps timestamps so they never decrease
+struct Monotonic {
+    /// latest timestamp written
+    last: Option<NaiveDateTime>,
+    /// the timestamps are behind `last`, the jump was already reported
+    behind: bool,
+}
+
+impl Monotonic {
+    /// the timestamp to write instead of `timestamp`, and how far back it jumped if it just did
+    fn stamp(&mut self, timestamp: NaiveDateTime) -> (NaiveDateTime, Option<chrono::Duration>) {
+        match self.last {
+            Some(last) if timestamp < last => {
+                let jump = (!self.behind).then(|| last - timestamp);
+                self.behind = true;
+                (last, jump)
+            }
+            _ => {
+                self.last = Some(timestamp);
+                self.behind = false;
+                (timestamp, None)
             }
         }
     }
 }
+
+/// opens a log file for appending, a new file gets a header with the requested format
+fn open_log_file(
+    path: &Path,
+    requested_format: Option<TimestampFormat>,
+) -> Result<(fs::File, TimestampFormat)> {
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("open log file for appending: `{path}`"))?;
+
+    let mut prefix = Vec::new();
+    (&mut file)
+        .take(MAX_HEADER_LEN as u64)
+        .read_to_end(&mut prefix)
+        .context("read log file header")?;
+    let format = if prefix.is_empty() {
+        // new file, record the format so readers don't have to assume it
+        let format = requested_format.unwrap_or_default();
+        let mut header = Vec::new();
+        format.serialize_header(&mut header);
+        file.write_all(&header).context("write log file header")?;
+        format
+    } else {
+        let format = TimestampFormat::from_file_prefix(&prefix)
+            .with_context(|| format!("read log file header: `{path}`"))?;
+        match requested_format {
+            Some(requested) if requested != format => bail!(
+                "log file `{path}` uses timestamp format `{}`, not `{}`",
+                format.as_str(),
+                requested.as_str(),
+            ),
+            _ => format,
+        }
+    };
+    Ok((file, format))
+}
+
+/// logs `input` in entries ending at newlines, a partial line is logged after `timeout` without
+/// more input
+fn log_lines(mut input: fs::File, timeout: Duration, log: &mut Log) -> Result<()> {
+    let mut pending = Vec::with_capacity(LOGENTRY_LIMIT);
+    // when input last added to the partial line in `pending`
+    let mut partial_since = None;
+    loop {
+        let wait = partial_since.map(|since: Instant| timeout.saturating_sub(since.elapsed()));
+        match poll_readable(input.as_fd(), wait) {
+            Ok(true) => {}
+            Ok(false) => {
+                // timed out, don't hold back the partial line any longer
+                log.write(&LogEntry::new(&pending))?;
+                pending.clear();
+                partial_since = None;
+                continue;
+            }
+            Err(err) if err.kind() == ErrorKind::Interrupted => {
+                log.reopen_if_requested()?;
+                continue;
+            }
+            Err(err) => return Err(err).context("wait for stdin"),
+        }
+
+        let len = pending.len();
+        pending.resize(LOGENTRY_LIMIT, 0);
+        let result = input.read(&mut pending[len..]);
+        pending.truncate(len + *result.as_ref().unwrap_or(&0));
+        match result {
+            Ok(0) => {
+                // EOF, log what's left even if it isn't a whole line
+                if !pending.is_empty() {
+                    log.write(&LogEntry::new(&pending))?;
+                }
+                return Ok(());
+            }
+            Ok(_) => {}
+            Err(err) if err.kind() == ErrorKind::Interrupted => {
+                log.reopen_if_requested()?;
+                continue;
+            }
+            Err(err) => return Err(err).context("read stdin"),
+        }
+
+        let end = lines_end(&pending);
+        if end > 0 {
+            log.write(&LogEntry::new(&pending[..end]))?;
+            pending.drain(..end);
+        }
+        // a line still coming in is held back, the entry size limits how long it can get
+        partial_since = (!pending.is_empty()).then(Instant::now);
+    }
+}
+
+/// how much of `pending` `--line-mode` logs as an entry: up to the last newline, or all of it
+/// when it's a single line filling a whole entry
+fn lines_end(pending: &[u8]) -> usize {
+    match pending.iter().rposition(|&byte| byte == b'\n') {
+        Some(newline) => newline + 1,
+        // the line doesn't fit into one entry, split it here
+        None if pending.len() == LOGENTRY_LIMIT => pending.len(),
+        None => 0,
+    }
+}
+
+/// logs the serialized entries read from `input` with their own timestamps
+///
+/// corrupt entries, unrecognized data and a truncated entry at the end fail unless `skip_corrupt`
+/// is set, then they are reported and skipped.
+fn log_frames(input: impl Read + Unpin, skip_corrupt: bool, log: &mut Log) -> Result<()> {
+    let mut entries = log::iter_entries(input);
+    let mut garbage_bytes = 0;
+    // reports the unrecognized input the reader skipped since the last call
+    let mut check_garbage = |log_reader: &LogReader| {
+        let skipped = log_reader.garbage_bytes - garbage_bytes;
+        garbage_bytes += skipped;
+        if skipped > 0 {
+            corrupt(
+                format_args!("{skipped} bytes of unrecognized input"),
+                skip_corrupt,
+            )?;
+        }
+        Ok::<_, anyhow::Error>(())
+    };
+    while let Some(entry) = entries.next() {
+        check_garbage(entries.log_reader())?;
+        match entry {
+            Ok(entry) => log.write(&entry)?,
+            Err(err @ ReadEntryError::DeserializeError(_)) => {
+                corrupt(format_args!("corrupt entry: {err}"), skip_corrupt)?;
+            }
+            Err(err) => return Err(err).context("read stdin"),
+        }
+    }
+    // skipped while looking for another entry at the end of the input
+    check_garbage(entries.log_reader())?;
+    let truncated = entries.log_reader().unconsumed();
+    if truncated > 0 {
+        corrupt(
+            format_args!("input ended in the middle of an entry, {truncated} bytes"),
+            skip_corrupt,
+        )?;
+    }
+    Ok(())
+}
+
+/// fails with `problem` or reports it if corrupt input is skipped
+fn corrupt(problem: fmt::Arguments<'_>, skip_corrupt: bool) -> Result<()> {
+    if !skip_corrupt {
+        bail!("{problem}, use `--skip-corrupt` to skip it");
+    }
+    eprintln!("skipping {problem}");
+    Ok(())
+}
+
+/// Retries reads interrupted by `SIGHUP`, the log is reopened before the next write anyway
+struct RetryInterrupted<R>(R);
+
+impl<R: Read> Read for RetryInterrupted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.0.read(buf) {
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                result => return result,
+            }
+        }
+    }
+}
+
+/// waits until `fd` is readable, returns `false` if `timeout` passed first
+///
+/// fails with [`ErrorKind::Interrupted`] when a signal arrived
+fn poll_readable(fd: BorrowedFd<'_>, timeout: Option<Duration>) -> io::Result<bool> {
+    let mut pollfd = libc::pollfd {
+        fd: fd.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    // round up, waking up early would just poll again
+    let timeout = timeout.map_or(-1, |timeout| {
+        timeout
+            .as_micros()
+            .div_ceil(1000)
+            .min(libc::c_int::MAX as u128) as libc::c_int
+    });
+    // SAFETY: `pollfd` is a valid array of one element
+    match unsafe { libc::poll(&mut pollfd, 1, timeout) } {
+        -1 => Err(io::Error::last_os_error()),
+        ready => Ok(ready > 0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn at(second: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd(2024, 1, 1).and_hms(0, 0, second)
+    }
+
+    #[test]
+    fn monotonic_holds_timestamps_until_they_catch_up() {
+        let mut monotonic = Monotonic {
+            last: None,
+            behind: false,
+        };
+        let seconds = chrono::Duration::seconds;
+        assert_eq!(monotonic.stamp(at(10)), (at(10), None));
+        // one marker per jump, however long the clock stays behind
+        assert_eq!(monotonic.stamp(at(5)), (at(10), Some(seconds(5))));
+        assert_eq!(monotonic.stamp(at(6)), (at(10), None));
+        assert_eq!(monotonic.stamp(at(2)), (at(10), None));
+        // equal isn't behind
+        assert_eq!(monotonic.stamp(at(10)), (at(10), None));
+        assert_eq!(monotonic.stamp(at(11)), (at(11), None));
+        // caught up, the next jump is reported again
+        assert_eq!(monotonic.stamp(at(3)), (at(11), Some(seconds(8))));
+        assert_eq!(monotonic.stamp(at(12)), (at(12), None));
+    }
+
+    #[test]
+    fn monotonic_starts_at_the_seed() {
+        let mut monotonic = Monotonic {
+            last: Some(at(30)),
+            behind: false,
+        };
+        assert_eq!(
+            monotonic.stamp(at(20)),
+            (at(30), Some(chrono::Duration::seconds(10)))
+        );
+    }
+
+    #[test]
+    fn lines_end_at_the_last_newline() {
+        assert_eq!(lines_end(b""), 0);
+        assert_eq!(lines_end(b"partial"), 0);
+        assert_eq!(lines_end(b"one\n"), 4);
+        assert_eq!(lines_end(b"one\ntwo\npartial"), 8);
+
+        // a full buffer is split at its last newline, the rest waits for the end of its line
+        let mut full = b"line\n".to_vec();
+        full.resize(LOGENTRY_LIMIT, b'x');
+        assert_eq!(lines_end(&full), 5);
+        *full.last_mut().unwrap() = b'\n';
+        assert_eq!(lines_end(&full), LOGENTRY_LIMIT);
+        // only a single line too long for an entry is cut
+        let long = vec![b'x'; LOGENTRY_LIMIT];
+        assert_eq!(lines_end(&long), LOGENTRY_LIMIT);
+    }
+}
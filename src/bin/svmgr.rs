@@ -1,13 +1,252 @@
-use clap::Parser;
+use anyhow::{bail, Context, Result};
+use camino::Utf8PathBuf as PathBuf;
+use clap::{AppSettings, ArgEnum, Parser, Subcommand};
+use std::fmt::Write;
+use std::os::unix::process::CommandExt;
+use std::{env, process};
+use svmgr::config::{self, ConfigError, Diagnostic, Severity, StandardOutput, UnitName};
 
 #[derive(Parser, Debug)]
+#[clap(setting = AppSettings::SubcommandRequiredElseHelp)]
 struct Args {
     /// If present `svmgr` starts in user mode for the given user
     #[clap(long)]
-    user: Option<String>,
+    user: Option<UnitName>,
+
+    /// Directory with the unit files, default is `/etc/sv` or `/etc/sv/{user}` in user mode
+    #[clap(long)]
+    config_dir: Option<PathBuf>,
+
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Check unit files for errors without loading them
+    #[clap(alias = "verify")]
+    Validate {
+        /// How to print the diagnostics
+        #[clap(long, arg_enum, default_value = "text")]
+        output: OutputFormat,
+
+        /// Unit files to check
+        files: Vec<PathBuf>,
+    },
+
+    /// List the units
+    List {
+        /// Only read the unit files, for when the daemon isn't running
+        ///
+        /// Invalid unit files are reported and the valid ones listed anyway.
+        #[clap(long)]
+        config_only: bool,
+    },
+
+    /// Print the log of a unit
+    Logs {
+        /// Keep printing new entries
+        #[clap(short, long)]
+        follow: bool,
+
+        unit: UnitName,
+    },
+}
+
+impl Args {
+    fn config_dir(&self) -> PathBuf {
+        match (&self.config_dir, &self.user) {
+            (Some(config_dir), _) => config_dir.clone(),
+            (None, Some(user)) => PathBuf::from("/etc/sv").join(user),
+            (None, None) => PathBuf::from("/etc/sv"),
+        }
+    }
+}
+
+#[derive(ArgEnum, Clone, Copy, Debug)]
+enum OutputFormat {
+    /// `file:line:column: severity: message` lines
+    Text,
+    /// JSON array of diagnostic objects
+    Json,
 }
 
-fn main() {
+fn main() -> Result<()> {
     let args = Args::parse();
-    dbg!(args);
+    match &args.command {
+        Command::Validate { output, files } => validate(*output, files),
+        Command::List { config_only } => list(&args, *config_only),
+        Command::Logs { follow, unit } => logs(&args, *follow, unit),
+    }
+}
+
+fn validate(output: OutputFormat, files: &[PathBuf]) -> Result<()> {
+    let diagnostics = files
+        .iter()
+        .flat_map(|file| config::validate_file(file))
+        .collect::<Vec<_>>();
+
+    match output {
+        OutputFormat::Text => {
+            for diagnostic in &diagnostics {
+                eprintln!("{diagnostic}");
+            }
+        }
+        OutputFormat::Json => println!("{}", diagnostics_json(&diagnostics)),
+    }
+
+    let errors = diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.severity == Severity::Error)
+        .count();
+    if errors > 0 {
+        bail!("found {errors} error(s)");
+    }
+    Ok(())
+}
+
+fn list(args: &Args, config_only: bool) -> Result<()> {
+    if !config_only {
+        bail!("there is no daemon to ask for the unit states, use `--config-only`");
+    }
+
+    let config_dir = args.config_dir();
+    let mut units = Vec::new();
+    let mut invalid = 0;
+    for loaded in config::load_dir(&config_dir)? {
+        match loaded {
+            Ok(loaded) => units.push(loaded),
+            Err(ConfigError::Validation { diagnostics, .. }) => {
+                invalid += 1;
+                for diagnostic in diagnostics {
+                    eprintln!("{diagnostic}");
+                }
+            }
+            Err(err) => {
+                invalid += 1;
+                eprintln!("{:#}", anyhow::Error::new(err));
+            }
+        }
+    }
+
+    let width = units
+        .iter()
+        .map(|loaded| loaded.name.as_str().len())
+        .chain(["NAME".len()])
+        .max()
+        .unwrap_or_default();
+    println!("{:width$}  {:7}  DESCRIPTION", "NAME", "TYPE");
+    for loaded in &units {
+        println!(
+            "{:width$}  {:7}  {}",
+            loaded.name.as_str(),
+            loaded.unit.unit_type().name(),
+            loaded.unit.description(),
+        );
+    }
+    eprintln!(
+        "{} unit(s) in `{config_dir}`, runtime state unavailable without the daemon",
+        units.len()
+    );
+
+    if invalid > 0 {
+        bail!("{invalid} unit file(s) could not be loaded");
+    }
+    Ok(())
+}
+
+/// replaces `svmgr` with `logread` for the unit's log
+fn logs(args: &Args, follow: bool, unit: &UnitName) -> Result<()> {
+    let file = args.config_dir().join(format!("{unit}.toml"));
+    let loaded = config::load_file(&file)?;
+    let standard_output = loaded.unit.standard_output();
+    if standard_output != StandardOutput::Capture {
+        bail!(
+            "unit `{unit}` has no captured logs, its output is set to `{}`",
+            standard_output.name()
+        );
+    }
+
+    let logread = env::current_exe()
+        .context("locate svmgr")?
+        .with_file_name("logread");
+    let mut command = process::Command::new(logread);
+    if follow {
+        command.arg("--follow");
+    }
+    command.arg(match &args.user {
+        Some(user) => format!("{user}/{unit}"),
+        None => unit.to_string(),
+    });
+    Err(command.exec()).context("run logread")
+}
+
+fn diagnostics_json(diagnostics: &[Diagnostic]) -> String {
+    let mut json = String::from("[");
+    for (i, diagnostic) in diagnostics.iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        json.push_str("{\"file\":");
+        json_string(&mut json, diagnostic.file.as_str());
+        match diagnostic.position {
+            Some((line, column)) => write!(json, ",\"line\":{line},\"column\":{column}").unwrap(),
+            None => json.push_str(",\"line\":null,\"column\":null"),
+        }
+        json.push_str(",\"severity\":");
+        json_string(&mut json, &diagnostic.severity.to_string());
+        json.push_str(",\"message\":");
+        json_string(&mut json, &diagnostic.message);
+        json.push('}');
+    }
+    json.push(']');
+    json
+}
+
+fn json_string(json: &mut String, value: &str) {
+    json.push('"');
+    for ch in value.chars() {
+        match ch {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            ch if ch.is_control() => write!(json, "\\u{:04x}", ch as u32).unwrap(),
+            ch => json.push(ch),
+        }
+    }
+    json.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_escapes_quotes_backslashes_and_control_characters() {
+        let diagnostics = [
+            Diagnostic {
+                file: PathBuf::from("/etc/sv/a \"b\".toml"),
+                position: Some((3, 7)),
+                severity: Severity::Error,
+                message: "expected `\\` or `\"`\nfound \t\u{1}".to_owned(),
+            },
+            Diagnostic {
+                file: PathBuf::from("/etc/sv/c.toml"),
+                position: None,
+                severity: Severity::Warning,
+                message: "unused".to_owned(),
+            },
+        ];
+        assert_eq!(
+            diagnostics_json(&diagnostics),
+            concat!(
+                r#"[{"file":"/etc/sv/a \"b\".toml","line":3,"column":7,"severity":"error","#,
+                r#""message":"expected `\\` or `\"`\nfound \t\u0001"},"#,
+                r#"{"file":"/etc/sv/c.toml","line":null,"column":null,"severity":"warning","#,
+                r#""message":"unused"}]"#,
+            )
+        );
+    }
 }
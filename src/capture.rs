@@ -0,0 +1,186 @@
+//! Capturing service output into the log
+//!
+//! A service writes its stdout and stderr into a pipe read by a `logwrite` process. The manager
+//! keeps its own copy of the read end, so when `logwrite` dies the pipe still has a reader and the
+//! service doesn't get `SIGPIPE`. Nobody reads the pipe until [`LogCapture::check`] starts a new
+//! `logwrite` though, once the pipe buffer is full the service blocks. The manager has to call
+//! `check` promptly, on `SIGCHLD` or periodically, then the output buffered in the pipe is picked
+//! up by the `logwrite` started in its place.
+//!
+//! Rotating the log is a handshake: [`LogCapture::rotate`] moves `current` aside, `logwrite` keeps
+//! appending to the moved file until it gets `SIGHUP` and reopens `current`, which creates a new
+//! file. Followers drain the moved file when `current` reappears, so nothing written before the
+//! switch is missed.
+
+use crate::config::{StandardOutput, Unit, UnitName};
+use crate::log;
+use camino::Utf8PathBuf as PathBuf;
+use std::io::{self, PipeReader, PipeWriter};
+use std::process::{Child, Command, ExitStatus, Stdio};
+
+/// starts capturing the output of `unit` into its log and wires it into `command`
+///
+/// returns `None` without starting `logwrite` when the unit doesn't want its output captured.
+pub fn capture_unit(
+    logwrite: impl Into<PathBuf>,
+    user: Option<&UnitName>,
+    name: &UnitName,
+    unit: &Unit,
+    command: &mut Command,
+) -> io::Result<Option<LogCapture>> {
+    if unit.standard_output() != StandardOutput::Capture {
+        return Ok(None);
+    }
+    let capture = LogCapture::new(logwrite, user, name)?;
+    command.stdout(capture.stdio()?).stderr(capture.stdio()?);
+    Ok(Some(capture))
+}
+
+pub struct LogCapture {
+    /// path of the `logwrite` binary
+    logwrite: PathBuf,
+    /// arguments selecting the log, passed to every `logwrite` we start
+    args: Vec<String>,
+    /// directory `logwrite` writes into
+    log_dir: PathBuf,
+    reader: PipeReader,
+    writer: PipeWriter,
+    logger: Child,
+}
+
+impl LogCapture {
+    /// creates the pipe and starts `logwrite` reading from it
+    pub fn new(
+        logwrite: impl Into<PathBuf>,
+        user: Option<&UnitName>,
+        tag: &UnitName,
+    ) -> io::Result<Self> {
+        Self::with_marker(logwrite, user, tag, None)
+    }
+
+    /// like [`LogCapture::new`], `logwrite` logs `marker` before any of the captured output
+    pub fn with_marker(
+        logwrite: impl Into<PathBuf>,
+        user: Option<&UnitName>,
+        tag: &UnitName,
+        marker: Option<&str>,
+    ) -> io::Result<Self> {
+        let logwrite = logwrite.into();
+        let args = logger_args(user, tag);
+        let (reader, writer) = io::pipe()?;
+        let logger = spawn_logger(&logwrite, &args, reader.try_clone()?.into(), marker)?;
+        Ok(LogCapture {
+            logwrite,
+            args,
+            log_dir: log::log_dir(user, tag),
+            reader,
+            writer,
+            logger,
+        })
+    }
+
+    /// write end of the pipe for the service's stdout or stderr
+    pub fn stdio(&self) -> io::Result<Stdio> {
+        Ok(Stdio::from(self.writer.try_clone()?))
+    }
+
+    /// checks whether `logwrite` is still running and starts a new one if it exited
+    ///
+    /// the manager calls this when it gets `SIGCHLD` or periodically while the service runs, the
+    /// service blocks on a full pipe until it does. returns the exit status of the old
+    /// `logwrite` when it had to be replaced, the new one logs a marker about the possible gap.
+    pub fn check(&mut self) -> io::Result<Option<ExitStatus>> {
+        let status = match self.logger.try_wait()? {
+            Some(status) => status,
+            None => return Ok(None),
+        };
+        let marker =
+            format!("svmgr: logger exited ({status}) and was restarted, output may have been lost");
+        self.logger = spawn_logger(
+            &self.logwrite,
+            &self.args,
+            self.reader.try_clone()?.into(),
+            Some(&marker),
+        )?;
+        Ok(Some(status))
+    }
+
+    /// process id of the running `logwrite`
+    pub fn logger_id(&self) -> u32 {
+        self.logger.id()
+    }
+
+    /// moves the current log file aside and tells `logwrite` to start a new one
+    ///
+    /// returns the path of the rotated file.
+    pub fn rotate(&self) -> io::Result<PathBuf> {
+        let rotated = log::rotate(&self.log_dir)?;
+        self.reopen()?;
+        Ok(rotated)
+    }
+
+    /// sends `SIGHUP` to `logwrite` so it reopens the log file
+    pub fn reopen(&self) -> io::Result<()> {
+        let pid = libc::pid_t::try_from(self.logger.id())
+            .map_err(|_| io::Error::from_raw_os_error(libc::ESRCH))?;
+        // SAFETY: `kill` doesn't touch our memory. the pid can't have been reused, our child is
+        // only reaped by `check` which replaces it right away
+        if unsafe { libc::kill(pid, libc::SIGHUP) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// closes the manager's ends of the pipe and waits for `logwrite` to finish
+    ///
+    /// `logwrite` exits once the service has closed its ends of the pipe as well.
+    pub fn finish(self) -> io::Result<ExitStatus> {
+        let LogCapture {
+            reader,
+            writer,
+            mut logger,
+            ..
+        } = self;
+        drop(writer);
+        drop(reader);
+        logger.wait()
+    }
+}
+
+/// logs `marker` as an entry of its own, waits until it's written
+pub fn log_marker(
+    logwrite: impl Into<PathBuf>,
+    user: Option<&UnitName>,
+    tag: &UnitName,
+    marker: &str,
+) -> io::Result<ExitStatus> {
+    let args = logger_args(user, tag);
+    spawn_logger(&logwrite.into(), &args, Stdio::null(), Some(marker))?.wait()
+}
+
+/// arguments selecting the log of `tag`
+fn logger_args(user: Option<&UnitName>, tag: &UnitName) -> Vec<String> {
+    let mut args = Vec::new();
+    if let Some(user) = user {
+        args.extend(["--user".to_owned(), user.to_string()]);
+    }
+    args.push(tag.to_string());
+    args
+}
+
+fn spawn_logger(
+    logwrite: &PathBuf,
+    args: &[String],
+    stdin: Stdio,
+    marker: Option<&str>,
+) -> io::Result<Child> {
+    let mut command = Command::new(logwrite);
+    if let Some(marker) = marker {
+        command.args(["--marker", marker]);
+    }
+    command
+        .args(args)
+        .stdin(stdin)
+        .stdout(Stdio::null())
+        .spawn()
+}
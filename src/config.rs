@@ -1,9 +1,24 @@
+use camino::{Utf8Path as Path, Utf8PathBuf as PathBuf};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::io::{self, ErrorKind};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::{env, fs};
+use thiserror::Error;
+use toml::Spanned;
 
 mod default {
     pub fn shell() -> String {
         "/bin/sh".to_owned()
     }
+
+    pub fn pass_environment() -> Vec<String> {
+        ["PATH", "HOME", "LANG", "USER", "LOGNAME"]
+            .map(String::from)
+            .to_vec()
+    }
 }
 
 /// Top level unit file structure
@@ -12,7 +27,8 @@ mod default {
 pub struct Unit {
     /// Human readable description
     ///
-    /// It's printed when listing units etc.
+    /// It's printed when listing units etc. Without one it's derived from the program or the first
+    /// line of the script when the unit is loaded.
     #[serde(default = "String::default")]
     description: String,
 
@@ -26,6 +42,98 @@ pub struct Unit {
     unit_type: Type,
 }
 
+/// longest description derived from a script, longer lines are cut off
+const DERIVED_DESCRIPTION_LEN: usize = 60;
+
+impl Unit {
+    /// fills in the values derived from the rest of the unit, after it was validated
+    fn normalize(&mut self) {
+        if self.description.is_empty() {
+            self.description = derived_description(self.unit_type.run());
+        }
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    pub fn shell(&self) -> &str {
+        &self.shell
+    }
+
+    pub fn unit_type(&self) -> &Type {
+        &self.unit_type
+    }
+
+    /// where the output of the unit's process goes, only services can opt out of capturing it
+    pub fn standard_output(&self) -> StandardOutput {
+        match &self.unit_type {
+            Type::Service(service) => service.standard_output,
+            Type::Timer(_) => StandardOutput::Capture,
+        }
+    }
+
+    /// Builds the command which starts the unit's process
+    ///
+    /// For `Shell` the configured shell is started with a piped stdin, the caller has to write the
+    /// script into it. Captured output has to be wired up by the caller as well, see
+    /// [`capture_unit`](crate::capture::capture_unit).
+    pub fn command(&self) -> io::Result<Command> {
+        let exec = |argv: &[String], key| {
+            let (program, args) = argv.split_first().ok_or_else(|| {
+                io::Error::new(ErrorKind::InvalidInput, format!("`{key}` is empty"))
+            })?;
+            let mut command = Command::new(program);
+            command.args(args);
+            Ok::<_, io::Error>(command)
+        };
+        let mut command = match self.unit_type.run() {
+            Run::Exec(argv) => exec(argv, "Exec")?,
+            Run::Command(line) => {
+                let argv = split_command(line).map_err(|err| {
+                    io::Error::new(ErrorKind::InvalidInput, format!("`Command`: {err}"))
+                })?;
+                exec(&argv, "Command")?
+            }
+            Run::Shell(_) => {
+                let mut command = Command::new(&self.shell);
+                command.stdin(Stdio::piped());
+                command
+            }
+        };
+        if let Type::Service(service) = &self.unit_type {
+            service.apply_environment(&mut command)?;
+        }
+        if self.standard_output() == StandardOutput::Null {
+            command.stdout(Stdio::null()).stderr(Stdio::null());
+        }
+        Ok(command)
+    }
+}
+
+/// name of the program for `Exec` and `Command`, the first line of a script which isn't empty or a
+/// comment for `Shell`
+fn derived_description(run: &Run) -> String {
+    let program = |argv0: &str| Path::new(argv0).file_name().unwrap_or(argv0).to_owned();
+    match run {
+        Run::Exec(argv) => argv.first().map(|argv0| program(argv0)),
+        Run::Command(line) => split_command(line)
+            .ok()
+            .and_then(|argv| argv.first().map(|argv0| program(argv0))),
+        Run::Shell(script) => script
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(
+                |line| match line.char_indices().nth(DERIVED_DESCRIPTION_LEN) {
+                    Some((end, _)) => format!("{}...", &line[..end]),
+                    None => line.to_owned(),
+                },
+            ),
+    }
+    .unwrap_or_default()
+}
+
 /// Ensures only one type of unit is configured
 #[derive(Serialize, Deserialize)]
 pub enum Type {
@@ -33,6 +141,23 @@ pub enum Type {
     Timer(Timer),
 }
 
+impl Type {
+    /// name of the unit type as it appears in the unit file
+    pub fn name(&self) -> &'static str {
+        match self {
+            Type::Service(_) => "Service",
+            Type::Timer(_) => "Timer",
+        }
+    }
+
+    pub fn run(&self) -> &Run {
+        match self {
+            Type::Service(service) => &service.run,
+            Type::Timer(timer) => &timer.run,
+        }
+    }
+}
+
 /// Ensures only one run variant is configured
 #[derive(Serialize, Deserialize)]
 pub enum Run {
@@ -41,6 +166,73 @@ pub enum Run {
 
     /// Use the configured shell to execute a script
     Shell(String),
+
+    /// Execute a command line split into arguments like a shell would, without running a shell
+    ///
+    /// Arguments are separated by whitespace, quotes and backslashes work as in `sh` but nothing
+    /// is expanded, see [`split_command`].
+    Command(String),
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum SplitCommandError {
+    #[error("unterminated {0} quote")]
+    UnterminatedQuote(&'static str),
+    #[error("trailing backslash")]
+    TrailingBackslash,
+}
+
+/// Splits a command line into arguments following the quoting rules of `sh`
+///
+/// Outside of quotes a backslash escapes the next character and a backslash-newline is removed.
+/// Single quotes keep everything literally, inside double quotes a backslash only escapes
+/// `` $ ` " \ `` and newline. There are no expansions, `$` and friends are ordinary characters.
+pub fn split_command(command: &str) -> Result<Vec<String>, SplitCommandError> {
+    let mut args = Vec::new();
+    // the argument being built, `None` between arguments so `''` can still produce an empty one
+    let mut arg: Option<String> = None;
+    let mut chars = command.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            ch if ch.is_ascii_whitespace() => {
+                args.extend(arg.take());
+            }
+            '\\' => match chars.next() {
+                Some('\n') => {}
+                Some(ch) => arg.get_or_insert_with(String::new).push(ch),
+                None => return Err(SplitCommandError::TrailingBackslash),
+            },
+            '\'' => {
+                let arg = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(ch) => arg.push(ch),
+                        None => return Err(SplitCommandError::UnterminatedQuote("single")),
+                    }
+                }
+            }
+            '"' => {
+                let arg = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('\n') => {}
+                            Some(ch @ ('$' | '`' | '"' | '\\')) => arg.push(ch),
+                            Some(ch) => arg.extend(['\\', ch]),
+                            None => return Err(SplitCommandError::UnterminatedQuote("double")),
+                        },
+                        Some(ch) => arg.push(ch),
+                        None => return Err(SplitCommandError::UnterminatedQuote("double")),
+                    }
+                }
+            }
+            ch => arg.get_or_insert_with(String::new).push(ch),
+        }
+    }
+    args.extend(arg);
+    Ok(args)
 }
 
 /// Service unit
@@ -51,6 +243,94 @@ pub enum Run {
 pub struct Service {
     #[serde(flatten)]
     run: Run,
+
+    /// Variables set for the process, they override the ones from `environment_file`
+    #[serde(default)]
+    environment: BTreeMap<String, String>,
+
+    /// File with `NAME=value` lines setting variables for the process
+    ///
+    /// Empty lines and lines starting with `#` are ignored.
+    #[serde(default)]
+    environment_file: Option<String>,
+
+    /// Start the process with an empty environment instead of inheriting the manager's
+    ///
+    /// Only the variables listed in `pass_environment` are inherited, the configured `environment`
+    /// and `environment_file` are applied as usual.
+    #[serde(default)]
+    clear_environment: bool,
+
+    /// Variables inherited from the manager when `clear_environment` is set
+    #[serde(default = "default::pass_environment")]
+    pass_environment: Vec<String>,
+
+    /// Where stdout and stderr of the process go
+    #[serde(default)]
+    standard_output: StandardOutput,
+}
+
+/// Destination of a service's stdout and stderr
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StandardOutput {
+    /// Captured into the service's log by `logwrite`
+    #[default]
+    Capture,
+    /// Inherited from the manager, for services which write their own logs
+    Inherit,
+    /// Discarded
+    Null,
+}
+
+impl StandardOutput {
+    /// value as it appears in the unit file
+    pub fn name(&self) -> &'static str {
+        match self {
+            StandardOutput::Capture => "capture",
+            StandardOutput::Inherit => "inherit",
+            StandardOutput::Null => "null",
+        }
+    }
+}
+
+impl Service {
+    /// sets up the process environment according to the configuration
+    fn apply_environment(&self, command: &mut Command) -> io::Result<()> {
+        if self.clear_environment {
+            command.env_clear();
+            for name in &self.pass_environment {
+                if let Some(value) = env::var_os(name) {
+                    command.env(name, value);
+                }
+            }
+        }
+        if let Some(path) = &self.environment_file {
+            command.envs(read_environment_file(Path::new(path))?);
+        }
+        command.envs(&self.environment);
+        Ok(())
+    }
+}
+
+/// parses `NAME=value` lines, ignoring empty lines and `#` comments
+fn read_environment_file(path: &Path) -> io::Result<Vec<(String, String)>> {
+    let contents = fs::read_to_string(path)?;
+    let mut variables = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (name, value) = line.split_once('=').ok_or_else(|| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                format!("{path}:{}: expected `NAME=value`", number + 1),
+            )
+        })?;
+        variables.push((name.trim_end().to_owned(), value.to_owned()));
+    }
+    Ok(variables)
 }
 
 /// Timer unit
@@ -65,3 +345,659 @@ pub struct Timer {
     /// Start immediately for the first time, don't wait for the first scheduled time
     on_startup: bool,
 }
+
+impl Timer {
+    pub fn on_startup(&self) -> bool {
+        self.on_startup
+    }
+}
+
+/// maximum length of a unit name in bytes
+pub const MAX_UNIT_NAME_LEN: usize = 128;
+
+#[derive(Error, Debug)]
+#[error("invalid unit name `{name}`: {reason}")]
+pub struct InvalidUnitName {
+    name: String,
+    reason: &'static str,
+}
+
+/// Name of a unit, it's also the tag of its log
+///
+/// Names end up as directory names in the log directory, so they're restricted to a single path
+/// component of printable ASCII. They're passed to `logwrite` and `logread` as arguments, so they
+/// can't start with `-` either.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct UnitName(String);
+
+impl UnitName {
+    pub fn new(name: &str) -> Result<UnitName, InvalidUnitName> {
+        let invalid = |reason| InvalidUnitName {
+            name: name.escape_debug().to_string(),
+            reason,
+        };
+
+        if name.is_empty() {
+            return Err(invalid("name is empty"));
+        }
+        if name.len() > MAX_UNIT_NAME_LEN {
+            return Err(invalid("name is too long"));
+        }
+        if name == "." || name == ".." {
+            return Err(invalid("name is a relative path"));
+        }
+        if name.starts_with('-') {
+            return Err(invalid("name starts with `-`"));
+        }
+        if name.contains('/') {
+            return Err(invalid("name contains a path separator"));
+        }
+        if !name.chars().all(|ch| ch.is_ascii_graphic()) {
+            return Err(invalid(
+                "name contains whitespace, control or non-ASCII characters",
+            ));
+        }
+        Ok(UnitName(name.to_owned()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// name of a unit file, which is its file name without extension
+    pub fn from_file(file: &Path) -> Result<UnitName, InvalidUnitName> {
+        UnitName::new(file.file_stem().unwrap_or_default())
+    }
+}
+
+impl FromStr for UnitName {
+    type Err = InvalidUnitName;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        UnitName::new(name)
+    }
+}
+
+impl Display for UnitName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl AsRef<Path> for UnitName {
+    fn as_ref(&self) -> &Path {
+        Path::new(&self.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
+
+/// Problem found in a unit file
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub file: PathBuf,
+    /// 1-based line and column, `None` when the problem concerns the whole file
+    pub position: Option<(usize, usize)>,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.position {
+            Some((line, column)) => write!(f, "{}:{line}:{column}: ", self.file)?,
+            None => write!(f, "{}: ", self.file)?,
+        }
+        write!(f, "{}: {}", self.severity, self.message)
+    }
+}
+
+/// Mirrors the [`Unit`] keys checked by [`validate_file`] to capture their positions
+///
+/// `Unit` itself can't carry spans because they get lost in `#[serde(flatten)]`.
+#[derive(Deserialize)]
+struct UnitSpans {
+    shell: Option<Spanned<String>>,
+    #[serde(rename = "Service")]
+    service: Option<RunSpans>,
+    #[serde(rename = "Timer")]
+    timer: Option<RunSpans>,
+}
+
+#[derive(Deserialize)]
+struct RunSpans {
+    #[serde(rename = "Exec")]
+    exec: Option<Spanned<Vec<String>>>,
+    #[serde(rename = "Command")]
+    command: Option<Spanned<String>>,
+    #[serde(rename = "Shell")]
+    shell: Option<Spanned<String>>,
+}
+
+/// converts a byte offset into a 1-based line and column
+fn line_column(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+    let column = before[line_start..].chars().count() + 1;
+    (line, column)
+}
+
+/// Checks a unit file and returns all problems found in it
+pub fn validate_file(file: &Path) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut report = |position, severity, message: String| {
+        diagnostics.push(Diagnostic {
+            file: file.to_owned(),
+            position,
+            severity,
+            message,
+        })
+    };
+
+    // the file name becomes the unit name
+    if let Err(err) = UnitName::from_file(file) {
+        report(None, Severity::Error, err.to_string());
+    }
+
+    let source = match fs::read_to_string(file) {
+        Ok(source) => source,
+        Err(err) => {
+            report(None, Severity::Error, format!("read unit file: {err}"));
+            return diagnostics;
+        }
+    };
+
+    if let Err(err) = toml::from_str::<Unit>(&source) {
+        let position = err.line_col().map(|(line, column)| (line + 1, column + 1));
+        report(position, Severity::Error, err.to_string());
+        return diagnostics;
+    }
+    diagnostics.extend(check_unit(file, &source));
+    diagnostics
+}
+
+/// checks the source of a unit file which already parsed as a [`Unit`]
+fn check_unit(file: &Path, source: &str) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut report = |position, severity, message: String| {
+        diagnostics.push(Diagnostic {
+            file: file.to_owned(),
+            position,
+            severity,
+            message,
+        })
+    };
+
+    let spans = match toml::from_str::<UnitSpans>(source) {
+        Ok(spans) => spans,
+        Err(err) => {
+            // shouldn't happen when the unit itself parsed
+            report(None, Severity::Error, err.to_string());
+            return diagnostics;
+        }
+    };
+
+    if let Some(shell) = &spans.shell {
+        if !shell.get_ref().starts_with('/') {
+            report(
+                Some(line_column(source, shell.start())),
+                Severity::Error,
+                format!(
+                    "`shell` must be an absolute path, got `{}`",
+                    shell.get_ref()
+                ),
+            );
+        }
+    }
+    for run in spans.service.iter().chain(&spans.timer) {
+        if let Some(exec) = &run.exec {
+            let position = Some(line_column(source, exec.start()));
+            match exec.get_ref().first() {
+                None => report(
+                    position,
+                    Severity::Error,
+                    "`Exec` must not be empty".to_owned(),
+                ),
+                Some(program) if program.is_empty() => report(
+                    position,
+                    Severity::Error,
+                    "`Exec` program must not be empty".to_owned(),
+                ),
+                Some(_) => {}
+            }
+        }
+        if let Some(command) = &run.command {
+            let position = Some(line_column(source, command.start()));
+            match split_command(command.get_ref()) {
+                Err(err) => report(position, Severity::Error, format!("`Command`: {err}")),
+                Ok(argv) if argv.is_empty() => report(
+                    position,
+                    Severity::Error,
+                    "`Command` must not be empty".to_owned(),
+                ),
+                Ok(argv) if argv[0].is_empty() => report(
+                    position,
+                    Severity::Error,
+                    "`Command` program must not be empty".to_owned(),
+                ),
+                Ok(_) => {}
+            }
+        }
+        if let Some(script) = &run.shell {
+            if script.get_ref().trim().is_empty() {
+                report(
+                    Some(line_column(source, script.start())),
+                    Severity::Warning,
+                    "`Shell` script is empty".to_owned(),
+                );
+            }
+        }
+    }
+
+    diagnostics
+}
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("read unit file `{path}`")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("read unit directory `{path}`")]
+    ReadDir {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("parse unit file `{path}`")]
+    Parse {
+        path: PathBuf,
+        #[source]
+        source: toml::de::Error,
+    },
+    #[error("unit file `{path}` is invalid, see `svmgr validate`")]
+    Validation {
+        path: PathBuf,
+        /// all problems found, at least one of them is an error
+        diagnostics: Vec<Diagnostic>,
+    },
+    #[error(transparent)]
+    InvalidName(#[from] InvalidUnitName),
+    #[error("unit `{name}` is defined by both `{first}` and `{second}`")]
+    DuplicateUnit {
+        name: UnitName,
+        first: PathBuf,
+        second: PathBuf,
+    },
+}
+
+impl ConfigError {
+    /// 1-based line and column of a parse error
+    pub fn position(&self) -> Option<(usize, usize)> {
+        match self {
+            ConfigError::Parse { source, .. } => source
+                .line_col()
+                .map(|(line, column)| (line + 1, column + 1)),
+            _ => None,
+        }
+    }
+}
+
+/// Unit read from a unit file
+pub struct LoadedUnit {
+    pub name: UnitName,
+    pub file: PathBuf,
+    pub unit: Unit,
+}
+
+/// Loads a unit file, the unit is named after the file
+///
+/// The file has to pass the same checks as [`validate_file`], warnings are ignored.
+pub fn load_file(file: &Path) -> Result<LoadedUnit, ConfigError> {
+    let name = UnitName::from_file(file)?;
+    let source = fs::read_to_string(file).map_err(|source| ConfigError::Io {
+        path: file.to_owned(),
+        source,
+    })?;
+    let mut unit = toml::from_str::<Unit>(&source).map_err(|source| ConfigError::Parse {
+        path: file.to_owned(),
+        source,
+    })?;
+    let diagnostics = check_unit(file, &source);
+    if diagnostics
+        .iter()
+        .any(|diagnostic| diagnostic.severity == Severity::Error)
+    {
+        return Err(ConfigError::Validation {
+            path: file.to_owned(),
+            diagnostics,
+        });
+    }
+    unit.normalize();
+    Ok(LoadedUnit {
+        name,
+        file: file.to_owned(),
+        unit,
+    })
+}
+
+/// Loads the `*.toml` unit files from all directories, missing directories are skipped
+///
+/// Unit names have to be unique across the directories.
+pub fn load_dirs(dirs: &[&Path]) -> Result<BTreeMap<UnitName, LoadedUnit>, ConfigError> {
+    let mut units = BTreeMap::<UnitName, LoadedUnit>::new();
+    for dir in dirs {
+        for loaded in load_dir(dir)? {
+            let loaded = loaded?;
+            if let Some(existing) = units.get(&loaded.name) {
+                return Err(ConfigError::DuplicateUnit {
+                    name: loaded.name,
+                    first: existing.file.clone(),
+                    second: loaded.file,
+                });
+            }
+            units.insert(loaded.name.clone(), loaded);
+        }
+    }
+    Ok(units)
+}
+
+/// Loads each `*.toml` unit file in `dir` on its own, sorted by file name
+///
+/// An invalid unit file doesn't keep the others from loading, only failing to read the directory
+/// itself is an error. A missing directory has no units.
+pub fn load_dir(dir: &Path) -> Result<Vec<Result<LoadedUnit, ConfigError>>, ConfigError> {
+    let io_error = |source| ConfigError::ReadDir {
+        path: dir.to_path_buf(),
+        source,
+    };
+    let read_dir = match dir.read_dir() {
+        Ok(read_dir) => read_dir,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(io_error(err)),
+    };
+    let mut files = Vec::new();
+    for dir_entry in read_dir {
+        let dir_entry = dir_entry.map_err(io_error)?;
+        // unit names are ASCII, anything else can't be a unit file
+        if let Ok(file) = PathBuf::from_path_buf(dir_entry.path()) {
+            if file.extension() == Some("toml") {
+                files.push(file);
+            }
+        }
+    }
+    files.sort();
+    Ok(files.iter().map(|file| load_file(file)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit(source: &str) -> Unit {
+        let mut unit = toml::from_str::<Unit>(source).unwrap();
+        unit.normalize();
+        unit
+    }
+
+    /// the environment the unit's process starts with
+    fn environment(unit: &Unit) -> BTreeMap<String, String> {
+        let output = unit.command().unwrap().output().unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .lines()
+            .filter_map(|line| line.split_once('='))
+            .map(|(name, value)| (name.to_owned(), value.to_owned()))
+            .collect()
+    }
+
+    /// a variable of the test process which isn't passed through explicitly
+    fn inherited_variable() -> (String, String) {
+        env::vars()
+            // `env` prints one variable per line
+            .find(|(name, value)| name != "PATH" && !value.contains('\n'))
+            .expect("the test runs with some environment")
+    }
+
+    /// the description of a service running `run`, given in unit file syntax
+    fn description_of(run: &str) -> String {
+        unit(&format!("[Service]\n{run}\n"))
+            .description()
+            .to_owned()
+    }
+
+    #[test]
+    fn description_is_derived_from_program() {
+        assert_eq!(
+            description_of(r#"Exec = ["/usr/sbin/nginx", "-g"]"#),
+            "nginx"
+        );
+        assert_eq!(description_of(r#"Exec = ["redis-server"]"#), "redis-server");
+        assert_eq!(
+            description_of(r#"Command = "'/opt/my app/bin/run' --port 80""#),
+            "run"
+        );
+    }
+
+    #[test]
+    fn description_is_derived_from_script() {
+        let script =
+            "Shell = \"\"\"\n\n  # start the worker\n\n  exec worker --queue jobs\nwait\n\"\"\"";
+        assert_eq!(description_of(script), "exec worker --queue jobs");
+        assert_eq!(description_of("Shell = \"# only a comment\""), "");
+
+        // cut off at 60 characters, not bytes
+        for (line, description) in [
+            ("a".repeat(60), "a".repeat(60)),
+            ("a".repeat(61), "a".repeat(60) + "..."),
+            ("\u{e9}".repeat(61), "\u{e9}".repeat(60) + "..."),
+        ] {
+            assert_eq!(description_of(&format!("Shell = \"{line}\"")), description);
+        }
+    }
+
+    #[test]
+    fn explicit_description_is_kept() {
+        let unit = unit(
+            r#"
+            description = "Web server"
+            [Service]
+            Exec = ["/usr/sbin/nginx"]
+            "#,
+        );
+        assert_eq!(unit.description(), "Web server");
+
+        // the derived one is stored like an explicit one
+        let unit = self::unit("[Service]\nExec = [\"/usr/sbin/nginx\"]\n");
+        let value = toml::Value::try_from(&unit).unwrap();
+        assert_eq!(value["description"].as_str(), Some("nginx"));
+    }
+
+    #[test]
+    fn commands_split_like_sh() {
+        let split = |command| split_command(command).unwrap();
+        assert_eq!(split("prog  -a\t-b\n"), ["prog", "-a", "-b"]);
+        assert!(split("  ").is_empty());
+        assert_eq!(split("echo 'a b' \"c d\""), ["echo", "a b", "c d"]);
+        assert_eq!(split("echo a\\ b c\\\\d"), ["echo", "a b", "c\\d"]);
+        assert_eq!(split("echo '' \"\" x''"), ["echo", "", "", "x"]);
+        assert_eq!(split("echo 'don'\\''t'"), ["echo", "don't"]);
+        // single quotes keep backslashes, double quotes only for a few characters
+        assert_eq!(split("echo '\\\"'"), ["echo", "\\\""]);
+        assert_eq!(split("echo \"\\$ \\\" \\n\""), ["echo", "$ \" \\n"]);
+        assert_eq!(split("echo a\\\nb \"c\\\nd\""), ["echo", "ab", "cd"]);
+        // nothing is expanded
+        assert_eq!(split("echo $HOME * ~"), ["echo", "$HOME", "*", "~"]);
+
+        let error = |command| split_command(command).unwrap_err();
+        assert_eq!(
+            error("echo 'a"),
+            SplitCommandError::UnterminatedQuote("single")
+        );
+        assert_eq!(
+            error("echo \"a"),
+            SplitCommandError::UnterminatedQuote("double")
+        );
+        assert_eq!(
+            error("echo \"a\\"),
+            SplitCommandError::UnterminatedQuote("double")
+        );
+        assert_eq!(error("echo a\\"), SplitCommandError::TrailingBackslash);
+    }
+
+    #[test]
+    fn command_runs_split_arguments() {
+        let unit = unit(
+            r#"
+            [Service]
+            Command = "printf '%s|' 'a b' c\\ d ''"
+            "#,
+        );
+        assert!(matches!(unit.unit_type().run(), Run::Command(_)));
+        // serialized as it was written, the splitting only happens when starting it
+        let value = toml::Value::try_from(&unit).unwrap();
+        assert_eq!(
+            value["Service"]["Command"].as_str(),
+            Some(r"printf '%s|' 'a b' c\ d ''")
+        );
+        let output = unit.command().unwrap().output().unwrap();
+        assert_eq!(output.stdout, b"a b|c d||");
+    }
+
+    #[test]
+    fn invalid_command_is_reported() {
+        for (command, message) in [
+            (r#""echo 'a""#, "`Command`: unterminated single quote"),
+            (r#""echo a\\""#, "`Command`: trailing backslash"),
+            (r#""  ""#, "`Command` must not be empty"),
+            (r#""'' a""#, "`Command` program must not be empty"),
+        ] {
+            let source = format!("[Service]\nCommand = {command}\n");
+            let diagnostics = check_unit(Path::new("test.toml"), &source);
+            let messages: Vec<_> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+            assert_eq!(messages, [message], "{command}");
+            assert_eq!(diagnostics[0].severity, Severity::Error);
+        }
+    }
+
+    #[test]
+    fn unit_names_are_single_path_components() {
+        for name in [
+            "web",
+            "a.b",
+            "x-1",
+            "_",
+            "...",
+            "@",
+            &"a".repeat(MAX_UNIT_NAME_LEN),
+        ] {
+            assert_eq!(UnitName::new(name).unwrap().as_str(), name);
+        }
+        for (name, reason) in [
+            ("", "name is empty"),
+            (&"a".repeat(MAX_UNIT_NAME_LEN + 1), "name is too long"),
+            (".", "name is a relative path"),
+            ("..", "name is a relative path"),
+            ("-web", "name starts with `-`"),
+            ("--help", "name starts with `-`"),
+            ("a/b", "name contains a path separator"),
+            ("/etc", "name contains a path separator"),
+            (
+                "a b",
+                "name contains whitespace, control or non-ASCII characters",
+            ),
+            (
+                "a\tb",
+                "name contains whitespace, control or non-ASCII characters",
+            ),
+            (
+                "caf\u{e9}",
+                "name contains whitespace, control or non-ASCII characters",
+            ),
+        ] {
+            assert_eq!(UnitName::new(name).unwrap_err().reason, reason, "{name:?}");
+        }
+        assert_eq!(
+            UnitName::from_file(Path::new("/etc/svmgr/web.toml")).unwrap(),
+            UnitName::new("web").unwrap(),
+        );
+    }
+
+    #[test]
+    fn directory_errors_name_the_directory() {
+        let dir = PathBuf::from_path_buf(env::temp_dir())
+            .unwrap()
+            .join(format!("svmgr-config-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        assert!(load_dir(&dir).unwrap().is_empty());
+
+        // a unit file which can't be read doesn't fail the directory
+        fs::create_dir_all(dir.join("unreadable.toml")).unwrap();
+        let loaded = load_dir(&dir).unwrap();
+        let err = loaded[0].as_ref().err().unwrap();
+        assert!(matches!(err, ConfigError::Io { .. }));
+        assert_eq!(
+            err.to_string(),
+            format!("read unit file `{dir}/unreadable.toml`")
+        );
+
+        let file = dir.join("file");
+        fs::write(&file, "").unwrap();
+        let err = load_dir(&file).err().unwrap();
+        assert!(matches!(err, ConfigError::ReadDir { .. }));
+        assert_eq!(err.to_string(), format!("read unit directory `{file}`"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn cleared_environment_keeps_only_configured_variables() {
+        let unit = unit(
+            r#"
+            [Service]
+            Exec = ["/usr/bin/env"]
+            clear_environment = true
+            pass_environment = ["SVMGR_PASSED", "SVMGR_NEVER_SET"]
+            environment = { SVMGR_CONFIGURED = "configured" }
+            "#,
+        );
+        // whatever else the test runs with, PATH included, must not get through
+        env::set_var("SVMGR_PASSED", "passed");
+        let expected = BTreeMap::from([
+            ("SVMGR_CONFIGURED".to_owned(), "configured".to_owned()),
+            ("SVMGR_PASSED".to_owned(), "passed".to_owned()),
+        ]);
+        assert_eq!(environment(&unit), expected);
+    }
+
+    #[test]
+    fn environment_is_inherited_by_default() {
+        let unit = unit(
+            r#"
+            [Service]
+            Exec = ["/usr/bin/env"]
+            environment = { SVMGR_CONFIGURED = "configured" }
+            "#,
+        );
+        let (inherited, value) = inherited_variable();
+        let environment = environment(&unit);
+        assert_eq!(environment[&inherited], value);
+        assert_eq!(environment["SVMGR_CONFIGURED"], "configured");
+    }
+}
@@ -1,2 +1,7 @@
+pub mod capture;
+pub mod cli;
 pub mod config;
 pub mod log;
+pub mod signal;
+pub mod supervisor;
+pub mod timer;
@@ -1,14 +1,44 @@
 //! Logging subsystem
 //!
 //! Logs are stored in `/var/log/sv/{unit}/current` for system services and
-//! `/var/log/sv/{user}/{unit}/current` for user services.
+//! `/var/log/sv/{user}/{unit}/current` for user services, the `SVMGR_LOG_DIR` environment variable
+//! moves them elsewhere. [`rotate`] moves `current` aside to a
+//! file named after the time of rotation, the writer reopens `current` when it gets `SIGHUP`.
+//!
+//! # Format
+//!
+//! A log file is a sequence of frames delimited by `FF FF FF FF` and `00 00 00 00`. Multi-byte
+//! integers are always little-endian, independent of the host, so log files can be moved between
+//! machines.
+//!
+//! - header: start marker, `svmgr-log`, format version `u8`, timestamp format string, end marker
+//! - entry: start marker, formatted timestamp, payload length `u16`, escaped payload, end marker
+//!
+//! The payload escapes `00` as `00 F0` and `FF` as `00 FF` so neither marker can occur inside it.
+//! The length field is not escaped, it can't contain a marker because entries are short enough for
+//! its high byte to never be `FF` and a zero length is the only way to get `00 00`. An empty
+//! entry therefore ends in six zeros, the reader takes the last four as the end marker.
+//!
+//! Any change to this layout has to bump `FORMAT_VERSION`.
 
-use chrono::{DateTime, Datelike, Local, NaiveDateTime, TimeZone};
-use std::io::{self, ErrorKind};
+use crate::config::UnitName;
+use camino::{Utf8Path as Path, Utf8PathBuf};
+use chrono::format::{Fixed, Item, StrftimeItems};
+use chrono::{DateTime, Datelike, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use std::collections::VecDeque;
+use std::env;
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::future::Future;
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom};
+use std::pin::{pin, Pin};
+use std::process::{Child, ChildStdout, Command, Stdio};
 use std::str;
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
 use std::{borrow::Cow, io::Write};
 use thiserror::Error;
-use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::io::{AsyncRead, AsyncReadExt, ReadBuf};
 
 /// Entry doesn't necessarily corespond to a single line, it corresponds to the amount a single
 /// call to `read` returns in case log buffering is disabled or up-to one buffer size in case it's
@@ -23,7 +53,10 @@ pub struct LogEntry<'a> {
 }
 
 const MAX_ENTRY_SIZE: usize = 4096;
-const DATE_FORMAT: &'static str = "%Y-%m-%d %H:%M:%S.%6f";
+// the length field is a `u16` whose high byte must not be `FF`
+const _: () = assert!(MAX_ENTRY_SIZE < 0xFF00);
+/// timestamp format used by files without a header
+const DATE_FORMAT: &str = "%Y-%m-%d %H:%M:%S.%6f";
 const DATE_LEN: usize =
       4 // %Y (checked at construction to be non-negative)
     + 1 // "-"
@@ -39,8 +72,16 @@ const DATE_LEN: usize =
     + 1 // "."
     + 6 // %6f
 ;
+/// maximum length of a formatted timestamp in any [`TimestampFormat`]
+const MAX_DATE_LEN: usize = 64;
+/// maximum length of the format string in any [`TimestampFormat`]
+const MAX_FORMAT_LEN: usize = 64;
 const SYNCHRONIZE_START: [u8; 4] = [0xFF; 4];
 const SYNCHRONIZE_END: [u8; 4] = [0x00; 4];
+/// identifies a file header frame, entry frames start with a timestamp instead
+const HEADER_MAGIC: &[u8] = b"svmgr-log";
+/// version of the file format written into the header
+const FORMAT_VERSION: u8 = 1;
 
 #[derive(Error, Debug)]
 pub enum DeserializeError {
@@ -52,12 +93,258 @@ pub enum DeserializeError {
     Utf8Error(#[from] str::Utf8Error),
     #[error("invalid timestamp")]
     InvalidTimestamp(#[from] chrono::format::ParseError),
+    #[error("timestamp year {0} is out of range")]
+    YearOutOfRange(i32),
     #[error("invalid escape, missing byte after 0x00")]
     InvalidEscape,
     #[error("missing synchronization prefix")]
     MissingSynchronizeStart,
     #[error("missing synchronization suffix")]
     MissingSynchronizeEnd,
+    #[error("unsupported log format version {0}")]
+    UnsupportedVersion(u8),
+    #[error("invalid timestamp format in header")]
+    InvalidTimestampFormat(#[from] InvalidTimestampFormat),
+}
+
+#[derive(Error, Debug)]
+#[error("invalid timestamp format `{format}`: {reason}")]
+pub struct InvalidTimestampFormat {
+    format: String,
+    reason: &'static str,
+}
+
+/// Layout of the timestamps in a log file
+///
+/// Writers record the format in the file header and readers pick it up from there, so the two
+/// never have to agree on it beforehand. Files without a header use the default format.
+///
+/// The formatted timestamp must have a fixed width because the reader splits it off the entry by
+/// length.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TimestampFormat {
+    format: Cow<'static, str>,
+    /// length of a formatted timestamp
+    len: usize,
+}
+
+impl Default for TimestampFormat {
+    fn default() -> Self {
+        TimestampFormat {
+            format: Cow::Borrowed(DATE_FORMAT),
+            len: DATE_LEN,
+        }
+    }
+}
+
+impl TimestampFormat {
+    /// validates a `chrono` strftime format string
+    ///
+    /// it has to round-trip through parsing and produce the same width for any timestamp, which is
+    /// checked on samples of every field value that changes the width of some format
+    pub fn new(format: &str) -> Result<TimestampFormat, InvalidTimestampFormat> {
+        let invalid = |reason| InvalidTimestampFormat {
+            format: format.to_owned(),
+            reason,
+        };
+
+        if format.len() > MAX_FORMAT_LEN {
+            return Err(invalid("format string is too long"));
+        }
+        if format.contains('\0') {
+            return Err(invalid("format string contains a NUL byte"));
+        }
+        if format.as_bytes().starts_with(HEADER_MAGIC) {
+            return Err(invalid("format string would be mistaken for a header"));
+        }
+        if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+            return Err(invalid("format string is not valid"));
+        }
+        if StrftimeItems::new(format).any(|item| needs_time_zone(&item)) {
+            return Err(invalid("timestamps are in UTC without a time zone"));
+        }
+
+        let mut len = None;
+        for sample in width_samples() {
+            let mut formatted = String::new();
+            // `to_string` would panic if `chrono` can't format an item
+            write!(formatted, "{}", sample.format(format))
+                .map_err(|_| invalid("timestamps can't be formatted"))?;
+            if *len.get_or_insert(formatted.len()) != formatted.len() {
+                return Err(invalid("timestamps don't have a fixed width"));
+            }
+            let parsed = NaiveDateTime::parse_from_str(&formatted, format)
+                .map_err(|_| invalid("timestamps can't be parsed back"))?;
+            if parsed.format(format).to_string() != formatted {
+                return Err(invalid("timestamps can't be parsed back"));
+            }
+        }
+        let len = len.unwrap();
+        if len > MAX_DATE_LEN {
+            return Err(invalid("timestamps are too long"));
+        }
+
+        Ok(TimestampFormat {
+            format: Cow::Owned(format.to_owned()),
+            len,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.format
+    }
+
+    fn is_default(&self) -> bool {
+        self.format == DATE_FORMAT
+    }
+
+    /// serializes the file header recording this format
+    pub fn serialize_header(&self, buffer: &mut Vec<u8>) {
+        buffer.extend(SYNCHRONIZE_START);
+        buffer.extend(HEADER_MAGIC);
+        buffer.push(FORMAT_VERSION);
+        buffer.extend(self.format.as_bytes());
+        buffer.extend(SYNCHRONIZE_END);
+    }
+
+    /// deserializes a file header frame, returns `Ok(None)` if the frame is not a header
+    pub fn deserialize_header(buffer: &[u8]) -> Result<Option<TimestampFormat>, DeserializeError> {
+        let buffer = buffer
+            .strip_prefix(&SYNCHRONIZE_START)
+            .ok_or(DeserializeError::MissingSynchronizeStart)?
+            .strip_suffix(&SYNCHRONIZE_END)
+            .ok_or(DeserializeError::MissingSynchronizeEnd)?;
+
+        let buffer = match buffer.strip_prefix(HEADER_MAGIC) {
+            Some(buffer) => buffer,
+            None => return Ok(None),
+        };
+        let (&version, format) = buffer
+            .split_first()
+            .ok_or(DeserializeError::NotEnoughInput)?;
+        if version != FORMAT_VERSION {
+            return Err(DeserializeError::UnsupportedVersion(version));
+        }
+        let format = str::from_utf8(format)?;
+        Ok(Some(TimestampFormat::new(format)?))
+    }
+
+    /// reads the format from the start of a log file
+    ///
+    /// `prefix` are the first bytes of the file, files without a header use the default format
+    pub fn from_file_prefix(prefix: &[u8]) -> Result<TimestampFormat, DeserializeError> {
+        let is_header = prefix
+            .strip_prefix(&SYNCHRONIZE_START)
+            .is_some_and(|rest| rest.starts_with(HEADER_MAGIC));
+        if !is_header {
+            return Ok(TimestampFormat::default());
+        }
+        let end = prefix
+            .windows(SYNCHRONIZE_END.len())
+            .position(|window| window == SYNCHRONIZE_END)
+            .ok_or(DeserializeError::NotEnoughInput)?;
+        let frame = &prefix[..end + SYNCHRONIZE_END.len()];
+        Ok(TimestampFormat::deserialize_header(frame)?.unwrap_or_default())
+    }
+}
+
+/// timestamps covering every value whose formatted width can vary: names of weekdays and months,
+/// one and two digit fields, the range of years and fractions with trailing zeros
+fn width_samples() -> Vec<NaiveDateTime> {
+    let midnight = |date: NaiveDate| date.and_hms_nano(0, 0, 0, 0);
+    let mut samples = Vec::new();
+    for year in [1970, 2000, 9999] {
+        for month in 1..=12 {
+            for day in [1, 9, 10, 28] {
+                samples.push(midnight(NaiveDate::from_ymd(year, month, day)));
+            }
+        }
+    }
+    // 2024-01-01 is a Monday, day 366 of 2024 the longest day of year
+    samples.extend((1..=7).map(|day| midnight(NaiveDate::from_ymd(2024, 1, day))));
+    samples.push(midnight(NaiveDate::from_ymd(2024, 12, 31)));
+
+    let date = NaiveDate::from_ymd(2024, 1, 1);
+    samples.extend((0..24).map(|hour| date.and_hms_nano(hour, 0, 0, 0)));
+    for value in [9, 10, 59] {
+        samples.push(date.and_hms_nano(0, value, 0, 0));
+        samples.push(date.and_hms_nano(0, 0, value, 0));
+    }
+    for nano in [1, 100_000_000, 123_456_789, 999_999_999] {
+        samples.push(date.and_hms_nano(0, 0, 0, nano));
+    }
+    samples.push(NaiveDate::from_ymd(9999, 12, 31).and_hms_nano(23, 59, 59, 999_999_999));
+    samples
+}
+
+/// whether formatting `item` needs a time zone, which the UTC timestamps of entries don't have
+fn needs_time_zone(item: &Item<'_>) -> bool {
+    let Item::Fixed(fixed) = item else {
+        return false;
+    };
+    matches!(
+        fixed,
+        Fixed::TimezoneName
+            | Fixed::TimezoneOffsetColon
+            | Fixed::TimezoneOffsetColonZ
+            | Fixed::TimezoneOffset
+            | Fixed::TimezoneOffsetZ
+            | Fixed::RFC2822
+            | Fixed::RFC3339
+    )
+    // `%#z` is internal, `chrono` panics formatting it
+    || StrftimeItems::new("%#z").any(|permissive| permissive == *item)
+}
+
+/// maximum length of a serialized file header
+pub const MAX_HEADER_LEN: usize =
+    SYNCHRONIZE_START.len() + HEADER_MAGIC.len() + 1 + MAX_FORMAT_LEN + SYNCHRONIZE_END.len();
+
+/// writes the entry length field, always little-endian
+fn write_len(len: usize, buffer: &mut Vec<u8>) {
+    let len = u16::try_from(len).expect("entry length fits the length field");
+    buffer.extend(len.to_le_bytes());
+}
+
+/// reads the entry length field, always little-endian
+fn read_len(bytes: [u8; 2]) -> usize {
+    usize::from(u16::from_le_bytes(bytes))
+}
+
+/// parses a timestamp in [`DATE_FORMAT`] without going through `chrono`'s general purpose parser
+///
+/// this runs for every entry read, returns `None` for anything unexpected so the caller can fall
+/// back to `chrono` to report the error
+fn parse_default_timestamp(bytes: &[u8]) -> Option<NaiveDateTime> {
+    let bytes: &[u8; DATE_LEN] = bytes.try_into().ok()?;
+    let digits = |range: std::ops::Range<usize>| {
+        bytes[range].iter().try_fold(0u32, |number, &byte| {
+            byte.is_ascii_digit()
+                .then(|| number * 10 + u32::from(byte - b'0'))
+        })
+    };
+    let separators = [
+        (4, b'-'),
+        (7, b'-'),
+        (10, b' '),
+        (13, b':'),
+        (16, b':'),
+        (19, b'.'),
+    ];
+    if separators
+        .iter()
+        .any(|&(index, separator)| bytes[index] != separator)
+    {
+        return None;
+    }
+
+    let date = NaiveDate::from_ymd_opt(digits(0..4)? as i32, digits(5..7)?, digits(8..10)?)?;
+    date.and_hms_micro_opt(
+        digits(11..13)?,
+        digits(14..16)?,
+        digits(17..19)?,
+        digits(20..26)?,
+    )
 }
 
 /// prevents either [`SYNCHRONIZE_END`] or [`SYNCHRONIZE_START`] from occuring in the message
@@ -109,38 +396,65 @@ impl<'a> LogEntry<'a> {
         }
     }
 
-    pub fn serialize(&self, buffer: &mut Vec<u8>) {
+    pub fn serialize(&self, format: &TimestampFormat, buffer: &mut Vec<u8>) {
         buffer.extend(SYNCHRONIZE_START);
         buffer
-            .write_fmt(format_args!("{}", self.timestamp.format(DATE_FORMAT)))
+            .write_fmt(format_args!("{}", self.timestamp.format(&format.format)))
             .unwrap();
         let entry = self.entry.as_ref();
-        let len = entry.len(); // length before escaping
-        buffer.extend(u16::to_le_bytes(len.try_into().unwrap()));
+        write_len(entry.len(), buffer); // length before escaping
         escape(self.entry.as_ref(), &mut *buffer);
         buffer.extend(SYNCHRONIZE_END); // synchronization suffix
     }
 
-    pub fn deserialize(buffer: &[u8]) -> Result<LogEntry<'_>, DeserializeError> {
+    pub fn deserialize<'b>(
+        buffer: &'b [u8],
+        format: &TimestampFormat,
+    ) -> Result<LogEntry<'b>, DeserializeError> {
         let buffer = buffer
             .strip_prefix(&SYNCHRONIZE_START)
             .ok_or(DeserializeError::MissingSynchronizeStart)?
             .strip_suffix(&SYNCHRONIZE_END)
             .ok_or(DeserializeError::MissingSynchronizeEnd)?;
 
-        if buffer.len() < DATE_LEN + 2 {
+        if buffer.len() < format.len + 2 {
             return Err(DeserializeError::NotEnoughInput);
         }
 
-        let (timestamp, rest) = buffer.split_at(DATE_LEN);
-        let timestamp = str::from_utf8(timestamp)?;
-        let timestamp = NaiveDateTime::parse_from_str(timestamp, DATE_FORMAT)?;
+        let (timestamp, rest) = buffer.split_at(format.len);
+        let fast = format
+            .is_default()
+            .then(|| parse_default_timestamp(timestamp))
+            .flatten();
+        let timestamp = match fast {
+            Some(timestamp) => timestamp,
+            None => {
+                let timestamp = str::from_utf8(timestamp)?;
+                let timestamp = NaiveDateTime::parse_from_str(timestamp, &format.format)?;
+                // other years are formatted wider and wouldn't fit the format's length again
+                if !(0..=9999).contains(&timestamp.year()) {
+                    return Err(DeserializeError::YearOutOfRange(timestamp.year()));
+                }
+                timestamp
+            }
+        };
 
         let (len, rest) = rest.split_at(2);
-        let len = usize::from(u16::from_le_bytes(len.try_into().unwrap()));
+        let len = read_len(len.try_into().unwrap());
+
+        if len == 0 {
+            // nothing to unescape, any payload contradicts the length
+            if !rest.is_empty() {
+                return Err(DeserializeError::TooMuchInput);
+            }
+            return Ok(LogEntry {
+                timestamp,
+                entry: Cow::Borrowed(rest),
+            });
+        }
 
-        if rest.len() > len * 2 {
-            // pre unescape check if there's too much input
+        if rest.len().div_ceil(2) > len {
+            // pre unescape check if there's too much input, every byte takes at most 2 escaped
             return Err(DeserializeError::TooMuchInput);
         }
 
@@ -148,7 +462,8 @@ impl<'a> LogEntry<'a> {
             // if the length matches there was no escaping so we don't need to unescape anything
             Cow::Borrowed(rest)
         } else {
-            let mut output = Vec::with_capacity(len);
+            // unescaping only shrinks, a corrupt length can't make us allocate more than the input
+            let mut output = Vec::with_capacity(len.min(rest.len()));
             unescape(rest, &mut output)?;
             // check the escaped input matches the declared length
             if output.len() > len {
@@ -166,6 +481,19 @@ impl<'a> LogEntry<'a> {
         Local.from_utc_datetime(&self.timestamp)
     }
 
+    /// timestamp in UTC timezone
+    pub fn timestamp(&self) -> NaiveDateTime {
+        self.timestamp
+    }
+
+    /// the same entry with a different timestamp, borrowing the bytes
+    pub fn with_timestamp(&self, timestamp: NaiveDateTime) -> LogEntry<'_> {
+        LogEntry {
+            timestamp,
+            entry: Cow::Borrowed(self.entry.as_ref()),
+        }
+    }
+
     pub fn as_slice(&self) -> &[u8] {
         self.entry.as_ref()
     }
@@ -174,7 +502,7 @@ impl<'a> LogEntry<'a> {
 /// buffer capacity for the [`LogReader`] is based on the maximum amount of space required to
 /// deserialize one [`LogEntry`], which is statically known
 const BUFFER_CAPACITY: usize = SYNCHRONIZE_START.len()
-    + DATE_LEN
+    + MAX_DATE_LEN
     + 2 // u16 for len of entry size
     + MAX_ENTRY_SIZE * 2 // all bytes were escaped and use 2 bytes per byte
     + SYNCHRONIZE_END.len();
@@ -185,10 +513,20 @@ pub struct LogReader {
     bytes: usize,
     /// length of the previous message
     last_len: usize,
+    /// timestamp format from the file header
+    format: TimestampFormat,
     /// reader has reached EOF before a synchronization point
     pub incomplete: bool,
     /// total bytes read from the input reader
     pub read_total: u64,
+    /// bytes discarded because they weren't part of any entry
+    pub garbage_bytes: u64,
+    /// synchronization prefixes without a matching suffix, usually corruption
+    pub false_starts: u64,
+    /// entries deserialized, never reset
+    entries_total: u64,
+    /// bytes read, never reset unlike `read_total`
+    bytes_total: u64,
 }
 
 impl LogReader {
@@ -208,13 +546,130 @@ impl LogReader {
         self.bytes -= amount;
     }
 
+    /// cumulative counters for computing [`ReadStats`]
+    pub fn counters(&self) -> ReadCounters {
+        ReadCounters {
+            entries: self.entries_total,
+            bytes: self.bytes_total,
+            garbage_bytes: self.garbage_bytes,
+            false_starts: self.false_starts,
+        }
+    }
+
+    /// timestamp format of the entries, taken from the last header the reader has seen
+    pub fn format(&self) -> &TimestampFormat {
+        &self.format
+    }
+
+    /// bytes read from the input which don't belong to a returned entry yet
+    ///
+    /// subtracting this from the input position gives the position right after the last entry,
+    /// where reading can be resumed later
+    pub fn unconsumed(&self) -> u64 {
+        (self.bytes - self.last_len) as u64
+    }
+
     pub fn new() -> LogReader {
         LogReader {
             buffer: Box::new([0; BUFFER_CAPACITY]),
             bytes: 0,
-            last_len: 0, // no message was read yet
+            last_len: 0,                        // no message was read yet
+            format: TimestampFormat::default(), // until we see a header
             incomplete: false,
             read_total: 0,
+            garbage_bytes: 0,
+            false_starts: 0,
+            entries_total: 0,
+            bytes_total: 0,
+        }
+    }
+}
+
+impl Default for LogReader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Cumulative counters of a [`LogReader`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ReadCounters {
+    pub entries: u64,
+    pub bytes: u64,
+    pub garbage_bytes: u64,
+    pub false_starts: u64,
+}
+
+impl ReadCounters {
+    /// counts since the `earlier` sample, saturating in case a counter was reset
+    pub fn since(&self, earlier: &ReadCounters) -> ReadCounters {
+        ReadCounters {
+            entries: self.entries.saturating_sub(earlier.entries),
+            bytes: self.bytes.saturating_sub(earlier.bytes),
+            garbage_bytes: self.garbage_bytes.saturating_sub(earlier.garbage_bytes),
+            false_starts: self.false_starts.saturating_sub(earlier.false_starts),
+        }
+    }
+
+    pub fn add(&mut self, other: &ReadCounters) {
+        self.entries += other.entries;
+        self.bytes += other.bytes;
+        self.garbage_bytes += other.garbage_bytes;
+        self.false_starts += other.false_starts;
+    }
+}
+
+/// Read throughput over a [`StatsWindow`]
+#[derive(Clone, Copy, Debug)]
+pub struct ReadStats {
+    /// time actually covered by the samples, shorter than the window until it fills up
+    pub elapsed: Duration,
+    pub entries_per_sec: f64,
+    pub bytes_per_sec: f64,
+    /// unrecognized bytes skipped in the window
+    pub garbage_bytes: u64,
+    /// truncated entries skipped in the window
+    pub false_starts: u64,
+}
+
+/// Rolling window of [`ReadCounters`] samples
+///
+/// The reader only bumps its counters, the rates are computed here when sampled, so there's no
+/// cost unless someone asks for the statistics.
+pub struct StatsWindow {
+    window: Duration,
+    samples: VecDeque<(Instant, ReadCounters)>,
+}
+
+impl StatsWindow {
+    pub fn new(window: Duration) -> Self {
+        StatsWindow {
+            window,
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// records a sample and returns the rates since the oldest sample within the window
+    pub fn sample(&mut self, now: Instant, counters: ReadCounters) -> ReadStats {
+        // keep one sample older than the window so it's always fully covered
+        while self.samples.len() > 1 && now.duration_since(self.samples[1].0) >= self.window {
+            self.samples.pop_front();
+        }
+        let (since, earlier) = self.samples.front().copied().unwrap_or((now, counters));
+        self.samples.push_back((now, counters));
+
+        let elapsed = now.duration_since(since);
+        let delta = counters.since(&earlier);
+        let per_sec = |count: u64| match elapsed.as_secs_f64() {
+            secs if secs > 0.0 => count as f64 / secs,
+            _ => 0.0,
+        };
+        ReadStats {
+            elapsed,
+            entries_per_sec: per_sec(delta.entries),
+            bytes_per_sec: per_sec(delta.bytes),
+            garbage_bytes: delta.garbage_bytes,
+            false_starts: delta.false_starts,
         }
     }
 }
@@ -243,6 +698,7 @@ impl LogReader {
             }
             Ok(n) => {
                 self.read_total += n as u64;
+                self.bytes_total += n as u64;
                 self.bytes += n;
                 Ok(())
             }
@@ -259,9 +715,10 @@ impl LogReader {
             let slice = &self.buffer[..self.bytes];
             if let Some(start_offset) = slice
                 .windows(4)
-                .position(|window| window == &SYNCHRONIZE_START)
+                .position(|window| window == SYNCHRONIZE_START)
             {
                 // shift the buffer to the left to drop unwanted bytes before the synchronization
+                self.garbage_bytes += start_offset as u64;
                 self.shift_buffer(start_offset);
                 // found it
                 break Ok(());
@@ -275,6 +732,7 @@ impl LogReader {
                 // sanity check: otherwise we would've found the pattern
                 assert!(useful_bytes < SYNCHRONIZE_START.len());
                 // fill the start manually as it's simpler
+                self.garbage_bytes += (self.bytes - useful_bytes) as u64;
                 self.buffer[..useful_bytes].fill(SYNCHRONIZE_START[0]);
                 self.bytes = useful_bytes;
                 // and try reading more bytes
@@ -301,9 +759,23 @@ impl LogReader {
             let slice = &self.buffer[offset..self.bytes];
             if let Some(end_offset) = slice
                 .windows(4)
-                .position(|window| window == &SYNCHRONIZE_END)
+                .position(|window| window == SYNCHRONIZE_END)
             {
-                break Ok(Some(offset + end_offset + SYNCHRONIZE_END.len()));
+                let end = offset + end_offset;
+                // an empty entry has a zero length field directly after the timestamp, together
+                // with SYNCHRONIZE_END that's six zeros and the frame only ends after the last four
+                let empty_entry_end = SYNCHRONIZE_START.len() + self.format.len + 2;
+                if end + 2 != empty_entry_end || self.buffer.len() < end + 6 {
+                    break Ok(Some(end + SYNCHRONIZE_END.len()));
+                }
+                if self.bytes < end + 6 {
+                    self.read_into_buffer(reader).await?;
+                    continue;
+                }
+                if self.buffer[end + 2..end + 6] == SYNCHRONIZE_END {
+                    break Ok(Some(end + 2 + SYNCHRONIZE_END.len()));
+                }
+                break Ok(Some(end + SYNCHRONIZE_END.len()));
             } else {
                 // we used the whole buffer and didn't find anything
                 if self.buffer.len() == self.bytes {
@@ -319,26 +791,701 @@ impl LogReader {
         }
     }
 
-    /// find and deserialize next entry
+    /// reads the file header from the start of `reader` to learn the timestamp format
     ///
+    /// used before seeking elsewhere in the file, the buffered bytes are discarded afterwards so
+    /// the reader can continue from any position. reaching EOF before a complete frame is not an
+    /// error, the header will be picked up by [`LogReader::next_entry`] when the file is read from
+    /// the start. the probe doesn't count towards the counters, the bytes are read again later.
+    pub async fn read_header<R>(&mut self, reader: &mut R) -> Result<(), ReadEntryError>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let counters = (
+            self.read_total,
+            self.bytes_total,
+            self.garbage_bytes,
+            self.false_starts,
+        );
+        let result = async {
+            self.synchronize_start(reader).await?;
+            if let Some(len) = self.synchronize_end(reader).await? {
+                if let Some(format) = TimestampFormat::deserialize_header(&self.buffer[..len])? {
+                    self.format = format;
+                }
+            }
+            Ok(())
+        }
+        .await;
+
+        self.bytes = 0;
+        self.last_len = 0;
+        (
+            self.read_total,
+            self.bytes_total,
+            self.garbage_bytes,
+            self.false_starts,
+        ) = counters;
+        match result {
+            Err(_) if self.incomplete => {
+                self.incomplete = false;
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    /// find and deserialize next entry
     ///
+    /// file headers are consumed transparently and switch the timestamp format for the following
+    /// entries
     pub async fn next_entry<R>(&mut self, reader: &mut R) -> Result<LogEntry<'_>, ReadEntryError>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let len = self.next_frame(reader).await?;
+        let entry = LogEntry::deserialize(&self.buffer[..len], &self.format)?;
+        self.entries_total += 1;
+        Ok(entry)
+    }
+
+    /// find the next entry and return its serialized frame, markers included
+    ///
+    /// the frame's timestamp is in [`LogReader::format`]. frames which don't deserialize are
+    /// skipped with an error like in [`LogReader::next_entry`], so corrupt data isn't passed on.
+    pub async fn next_raw_entry<R>(&mut self, reader: &mut R) -> Result<&[u8], ReadEntryError>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let len = self.next_valid_frame(reader).await?;
+        Ok(&self.buffer[..len])
+    }
+
+    /// finds the next entry frame which deserializes, returns its length
+    async fn next_valid_frame<R>(&mut self, reader: &mut R) -> Result<usize, ReadEntryError>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let len = self.next_frame(reader).await?;
+        LogEntry::deserialize(&self.buffer[..len], &self.format)?;
+        self.entries_total += 1;
+        Ok(len)
+    }
+
+    /// finds the next entry frame, consuming headers on the way, returns its length
+    async fn next_frame<R>(&mut self, reader: &mut R) -> Result<usize, ReadEntryError>
     where
         R: AsyncRead + Unpin,
     {
         // discard the previous message bytes
         self.shift_buffer(self.last_len);
+        self.last_len = 0;
         loop {
             self.synchronize_start(reader).await?;
             if let Some(len) = self.synchronize_end(reader).await? {
+                // frames never contain SYNCHRONIZE_START, if there is another one the current
+                // start was false and the frame begins there
+                if let Some(offset) = self.buffer[1..len]
+                    .windows(4)
+                    .position(|window| window == SYNCHRONIZE_START)
+                {
+                    let discard = 1 + offset;
+                    // overlapping markers are just a longer run of 0xFF before the real one
+                    if discard >= SYNCHRONIZE_START.len() {
+                        self.false_starts += 1;
+                    }
+                    self.garbage_bytes += discard as u64;
+                    self.shift_buffer(discard);
+                    continue;
+                }
+                // the frame is consumed even if it fails to deserialize
                 self.last_len = len;
-                return LogEntry::deserialize(&self.buffer[..len]).map_err(<_>::from);
+                if let Some(format) = TimestampFormat::deserialize_header(&self.buffer[..len])? {
+                    self.format = format;
+                    self.shift_buffer(len);
+                    self.last_len = 0;
+                    continue;
+                }
+                return Ok(len);
             } else {
                 // we couldn't find SYNCHRONIZE_END within the expected distance of
                 // SYNCHRONIZE_START, discard the current SYNCHRONIZE_START and try synchronizing
                 // again
+                self.false_starts += 1;
+                self.garbage_bytes += SYNCHRONIZE_START.len() as u64;
                 self.shift_buffer(SYNCHRONIZE_START.len());
             }
         }
     }
 }
+
+/// Adapts a blocking reader to [`AsyncRead`], every read completes immediately
+struct BlockingReader<R>(R);
+
+impl<R: Read + Unpin> AsyncRead for BlockingReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let result = self.0.read(buf.initialize_unfilled());
+        Poll::Ready(result.map(|n| buf.advance(n)))
+    }
+}
+
+/// drives a future which only awaits a [`BlockingReader`] to completion
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    match future
+        .as_mut()
+        .poll(&mut Context::from_waker(Waker::noop()))
+    {
+        Poll::Ready(output) => output,
+        Poll::Pending => unreachable!("blocking reader never returns pending"),
+    }
+}
+
+/// Synchronous iterator over the entries of a log, see [`iter_entries`]
+pub struct Entries<R> {
+    reader: BlockingReader<R>,
+    log_reader: LogReader,
+    done: bool,
+}
+
+/// iterates over the entries read from a blocking reader
+///
+/// entries which fail to deserialize are returned as errors and skipped, the iteration ends at EOF
+/// or after the first I/O error
+pub fn iter_entries<R: Read + Unpin>(reader: R) -> Entries<R> {
+    Entries {
+        reader: BlockingReader(reader),
+        log_reader: LogReader::new(),
+        done: false,
+    }
+}
+
+impl<R> Entries<R> {
+    /// the underlying reader, for its counters
+    pub fn log_reader(&self) -> &LogReader {
+        &self.log_reader
+    }
+}
+
+impl<R: Read + Unpin> Entries<R> {
+    /// like [`Iterator::next`] but returns the serialized frame, see [`LogReader::next_raw_entry`]
+    pub fn next_raw(&mut self) -> Option<Result<Vec<u8>, ReadEntryError>> {
+        if self.done {
+            return None;
+        }
+        let result = block_on(self.log_reader.next_valid_frame(&mut self.reader));
+        match result {
+            Ok(len) => Some(Ok(self.log_reader.buffer[..len].to_vec())),
+            Err(_) if self.log_reader.incomplete => {
+                self.done = true;
+                None
+            }
+            Err(err @ ReadEntryError::DeserializeError(_)) => Some(Err(err)),
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+impl<R: Read + Unpin> Iterator for Entries<R> {
+    type Item = Result<LogEntry<'static>, ReadEntryError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let result =
+            block_on(self.log_reader.next_entry(&mut self.reader)).map(|entry| entry.to_owned());
+        match result {
+            Ok(entry) => Some(Ok(entry)),
+            Err(_) if self.log_reader.incomplete => {
+                self.done = true;
+                None
+            }
+            Err(err @ ReadEntryError::DeserializeError(_)) => Some(Err(err)),
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+/// Compression of a rotated log file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// detects the compression from the magic bytes at the start of a file
+    pub fn detect(prefix: &[u8]) -> Compression {
+        if prefix.starts_with(&[0x1F, 0x8B]) {
+            Compression::Gzip
+        } else if prefix.starts_with(&[0x28, 0xB5, 0x2F, 0xFD]) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+
+    /// external program which decompresses stdin to stdout
+    fn decompressor(self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some("gzip"),
+            Compression::Zstd => Some("zstd"),
+        }
+    }
+}
+
+/// Reader over a log file which transparently decompresses it
+pub enum LogFile {
+    Plain(File),
+    Compressed {
+        program: &'static str,
+        child: Child,
+        stdout: ChildStdout,
+    },
+}
+
+impl LogFile {
+    /// opens a log file, compressed files are piped through the decompressor
+    pub fn open(path: &Path) -> io::Result<LogFile> {
+        let mut file = File::open(path)?;
+        let mut prefix = [0; 4];
+        let mut filled = 0;
+        while filled < prefix.len() {
+            match file.read(&mut prefix[filled..])? {
+                0 => break,
+                n => filled += n,
+            }
+        }
+        file.seek(SeekFrom::Start(0))?;
+
+        let program = match Compression::detect(&prefix[..filled]).decompressor() {
+            Some(program) => program,
+            None => return Ok(LogFile::Plain(file)),
+        };
+        let mut child = Command::new(program)
+            .args(["-d", "-c"])
+            .stdin(file)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            // not `NotFound`, callers take that to mean the log file is gone
+            .map_err(|err| io::Error::other(format!("start decompressor `{program}`: {err}")))?;
+        let stdout = child.stdout.take().expect("stdout is piped");
+        Ok(LogFile::Compressed {
+            program,
+            child,
+            stdout,
+        })
+    }
+}
+
+impl Read for LogFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            LogFile::Plain(file) => file.read(buf),
+            LogFile::Compressed {
+                program,
+                child,
+                stdout,
+            } => {
+                let n = stdout.read(buf)?;
+                if n == 0 && !buf.is_empty() {
+                    // a corrupt or truncated file only shows in the exit status
+                    let status = child.wait()?;
+                    if !status.success() {
+                        return Err(io::Error::new(
+                            ErrorKind::InvalidData,
+                            format!("`{program}` failed to decompress the file: {status}"),
+                        ));
+                    }
+                }
+                Ok(n)
+            }
+        }
+    }
+}
+
+impl Drop for LogFile {
+    fn drop(&mut self) {
+        if let LogFile::Compressed { child, .. } = self {
+            // we may stop reading early, don't leave the decompressor behind
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+/// directory with the logs of all units
+pub const LOG_DIR: &str = "/var/log/sv";
+
+/// [`LOG_DIR`] unless the `SVMGR_LOG_DIR` environment variable points elsewhere
+pub fn base_dir() -> Utf8PathBuf {
+    match env::var("SVMGR_LOG_DIR") {
+        Ok(dir) if !dir.is_empty() => Utf8PathBuf::from(dir),
+        _ => Utf8PathBuf::from(LOG_DIR),
+    }
+}
+
+/// directory of a unit's log, `/var/log/sv/{tag}` or `/var/log/sv/{user}/{tag}` for user units
+pub fn log_dir(user: Option<&UnitName>, tag: &UnitName) -> Utf8PathBuf {
+    let base_path = base_dir();
+    match user {
+        Some(user) => base_path.join(user).join(tag),
+        None => base_path.join(tag),
+    }
+}
+
+/// Moves `current` in a log directory aside, returns the path of the rotated file
+///
+/// The writer keeps appending to the rotated file until it's told to reopen `current`, readers
+/// following the log switch to the new `current` once it's created. Rotated files are named
+/// `@{UTC timestamp}` so they sort chronologically.
+pub fn rotate(log_dir: &Path) -> io::Result<Utf8PathBuf> {
+    let name = format!("@{}", Utc::now().format("%Y%m%dT%H%M%S%.6fZ"));
+    let rotated = log_dir.join(name);
+    if rotated.exists() {
+        return Err(io::Error::new(
+            ErrorKind::AlreadyExists,
+            format!("rotated log file `{rotated}` already exists"),
+        ));
+    }
+    fs::rename(log_dir.join("current"), &rotated)?;
+    Ok(rotated)
+}
+
+/// timestamp of the last entry in a log file whose entries are in `format`, `None` if it has none
+///
+/// only the end of the file is read, as much as the longest possible entry. the position of `file`
+/// is left at the end.
+pub fn last_timestamp(
+    file: &mut File,
+    format: &TimestampFormat,
+) -> io::Result<Option<NaiveDateTime>> {
+    let len = file.seek(SeekFrom::End(0))?;
+    // starting in the middle of an entry is fine, the reader skips to the next frame
+    file.seek(SeekFrom::Start(len.saturating_sub(BUFFER_CAPACITY as u64)))?;
+    let mut entries = iter_entries(&mut *file);
+    entries.log_reader.format = format.clone();
+    let mut last = None;
+    for entry in &mut entries {
+        match entry {
+            Ok(entry) => last = Some(entry.timestamp),
+            Err(ReadEntryError::DeserializeError(_)) => continue,
+            Err(ReadEntryError::IoError(err)) => return Err(err),
+        }
+    }
+    Ok(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn timestamp(date: (i32, u32, u32), time: (u32, u32, u32, u32)) -> NaiveDateTime {
+        NaiveDate::from_ymd(date.0, date.1, date.2).and_hms_micro(time.0, time.1, time.2, time.3)
+    }
+
+    #[test]
+    fn variable_width_formats_are_rejected() {
+        for format in [
+            "%Y-%m-%d %H:%M:%S.%6f %A %-d %-d",
+            "%Y-%m-%d %-H:%M:%S",
+            "%Y-%m-%d %H:%M:%S %B",
+            "%Y-%m-%d %H:%M:%S%.f",
+            "%s",
+        ] {
+            let err = TimestampFormat::new(format).unwrap_err();
+            assert_eq!(
+                err.reason, "timestamps don't have a fixed width",
+                "{format}"
+            );
+        }
+    }
+
+    #[test]
+    fn fixed_width_formats_are_accepted() {
+        for format in [
+            DATE_FORMAT,
+            "%d.%m.%Y %H:%M:%S%.3f",
+            "%a %b %d %H:%M:%S %Y",
+            "%Y%m%dT%H%M%S",
+        ] {
+            TimestampFormat::new(format).unwrap();
+        }
+    }
+
+    #[test]
+    fn reader_detects_format_from_header() {
+        let format = TimestampFormat::new("%d.%m.%Y %H:%M:%S%.3f").unwrap();
+        let timestamps = [
+            timestamp((2024, 1, 1), (0, 0, 0, 0)),
+            timestamp((2024, 12, 31), (23, 59, 59, 999_000)),
+        ];
+        let mut file = Vec::new();
+        format.serialize_header(&mut file);
+        for (i, timestamp) in timestamps.iter().enumerate() {
+            let payload = format!("entry {i}");
+            LogEntry::new(payload.as_bytes())
+                .with_timestamp(*timestamp)
+                .serialize(&format, &mut file);
+        }
+
+        let mut entries = iter_entries(Cursor::new(file));
+        for (i, timestamp) in timestamps.iter().enumerate() {
+            let entry = entries.next().unwrap().unwrap();
+            assert_eq!(entry.timestamp(), *timestamp);
+            assert_eq!(entry.as_slice(), format!("entry {i}").as_bytes());
+        }
+        assert!(entries.next().is_none());
+        assert_eq!(*entries.log_reader().format(), format);
+    }
+
+    #[test]
+    fn time_zone_header_is_an_error() {
+        for format in [
+            "%Y-%m-%d %H:%M:%S %z",
+            "%Y-%m-%d %H:%M:%S %#z",
+            "%Z %Y",
+            "%+",
+        ] {
+            let mut frame = SYNCHRONIZE_START.to_vec();
+            frame.extend(HEADER_MAGIC);
+            frame.push(FORMAT_VERSION);
+            frame.extend(format.as_bytes());
+            frame.extend(SYNCHRONIZE_END);
+
+            let err = TimestampFormat::deserialize_header(&frame).unwrap_err();
+            assert!(
+                matches!(err, DeserializeError::InvalidTimestampFormat(_)),
+                "{format}: {err:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn negative_year_is_out_of_range() {
+        for format in [
+            TimestampFormat::default(),
+            TimestampFormat::new("%Y-%m-%d %H:%M:%S").unwrap(),
+        ] {
+            let mut frame = Vec::new();
+            LogEntry::new(b"entry")
+                .with_timestamp(timestamp((2024, 1, 1), (12, 0, 0, 0)))
+                .serialize(&format, &mut frame);
+            // same width as the year it replaces
+            let start = SYNCHRONIZE_START.len();
+            frame[start..start + 4].copy_from_slice(b"-999");
+
+            let err = match LogEntry::deserialize(&frame, &format) {
+                Ok(_) => panic!("{} accepted a negative year", format.as_str()),
+                Err(err) => err,
+            };
+            assert!(
+                matches!(err, DeserializeError::YearOutOfRange(-999)),
+                "{}: {err:?}",
+                format.as_str()
+            );
+        }
+    }
+
+    #[test]
+    fn junk_before_a_frame_is_counted() {
+        let mut file = b"junk\xFF\xFF".to_vec();
+        LogEntry::new(b"first").serialize(&TimestampFormat::default(), &mut file);
+        // a start marker whose frame never ends, up to the next frame
+        file.extend(SYNCHRONIZE_START);
+        file.extend(b"cut off");
+        LogEntry::new(b"second").serialize(&TimestampFormat::default(), &mut file);
+
+        let mut entries = iter_entries(Cursor::new(file));
+        assert_eq!(entries.next().unwrap().unwrap().as_slice(), b"first");
+        assert_eq!(entries.log_reader().garbage_bytes, 6);
+        assert_eq!(entries.log_reader().false_starts, 0);
+        assert_eq!(entries.next().unwrap().unwrap().as_slice(), b"second");
+        assert_eq!(entries.log_reader().garbage_bytes, 6 + 4 + 7);
+        assert_eq!(entries.log_reader().false_starts, 1);
+        assert!(entries.next().is_none());
+    }
+
+    #[test]
+    fn header_probe_is_not_counted() {
+        let format = TimestampFormat::new("%d.%m.%Y %H:%M:%S%.3f").unwrap();
+        let mut file = b"junk".to_vec();
+        format.serialize_header(&mut file);
+        LogEntry::new(b"entry").serialize(&format, &mut file);
+
+        let mut reader = LogReader::new();
+        block_on(reader.read_header(&mut BlockingReader(Cursor::new(&file)))).unwrap();
+        assert_eq!(*reader.format(), format);
+        assert_eq!(reader.counters(), ReadCounters::default());
+        assert_eq!(reader.read_total, 0);
+
+        // reading the file afterwards counts every byte once
+        let mut input = BlockingReader(Cursor::new(&file));
+        block_on(reader.next_entry(&mut input)).unwrap();
+        let counters = reader.counters();
+        assert_eq!(counters.garbage_bytes, 4);
+        assert_eq!(counters.bytes, file.len() as u64);
+        assert_eq!(reader.read_total, file.len() as u64);
+    }
+
+    #[test]
+    fn fast_timestamp_parser_agrees_with_chrono() {
+        let chrono = |timestamp: &str| NaiveDateTime::parse_from_str(timestamp, DATE_FORMAT);
+        for sample in width_samples() {
+            let formatted = sample.format(DATE_FORMAT).to_string();
+            let fast = parse_default_timestamp(formatted.as_bytes());
+            assert_eq!(fast, Some(chrono(&formatted).unwrap()), "{formatted}");
+        }
+        for invalid in [
+            "2024-02-30 00:00:00.000000",
+            "2023-02-29 00:00:00.000000",
+            "2024-13-01 00:00:00.000000",
+            "2024-00-01 00:00:00.000000",
+            "2024-01-01 24:00:00.000000",
+            "2024-01-01 00:60:00.000000",
+            "2024-01-01T00:00:00.000000",
+            "2024-01-01 00:00:00,000000",
+            "2024-01-01 00:00:00.00000a",
+            "2024-01-01 00:00:00.0000000",
+        ] {
+            assert_eq!(
+                parse_default_timestamp(invalid.as_bytes()),
+                None,
+                "{invalid}"
+            );
+            assert!(chrono(invalid).is_err(), "{invalid}");
+        }
+        // leap seconds are left to the fallback
+        let leap = "2016-12-31 23:59:60.000000";
+        assert_eq!(parse_default_timestamp(leap.as_bytes()), None);
+        let mut frame = vec![0xFF; 4];
+        frame.extend(leap.as_bytes());
+        frame.extend([0x00; 2 + 4]);
+        let entry = LogEntry::deserialize(&frame, &TimestampFormat::default()).unwrap();
+        assert_eq!(entry.timestamp(), chrono(leap).unwrap());
+    }
+
+    #[test]
+    fn entry_frame_layout() {
+        let timestamp = timestamp((2024, 1, 2), (3, 4, 5, 678_901));
+        // 0x0102 tells the byte order apart
+        let payload = [b'a'; 0x0102];
+        let mut frame = vec![0xFF; 4];
+        frame.extend(b"2024-01-02 03:04:05.678901");
+        frame.extend([0x02, 0x01]);
+        frame.extend(payload);
+        frame.extend([0x00; 4]);
+
+        let mut serialized = Vec::new();
+        LogEntry::new(&payload)
+            .with_timestamp(timestamp)
+            .serialize(&TimestampFormat::default(), &mut serialized);
+        assert_eq!(serialized, frame);
+
+        let entry = LogEntry::deserialize(&frame, &TimestampFormat::default()).unwrap();
+        assert_eq!(entry.timestamp(), timestamp);
+        assert_eq!(entry.as_slice(), payload);
+    }
+
+    #[test]
+    fn empty_entry_frame() {
+        let timestamp = timestamp((2024, 1, 2), (3, 4, 5, 678_901));
+        let mut frame = vec![0xFF; 4];
+        frame.extend(b"2024-01-02 03:04:05.678901");
+        frame.extend([0x00; 2 + 4]);
+
+        let mut serialized = Vec::new();
+        LogEntry::new(b"")
+            .with_timestamp(timestamp)
+            .serialize(&TimestampFormat::default(), &mut serialized);
+        assert_eq!(serialized, frame);
+
+        let entry = LogEntry::deserialize(&frame, &TimestampFormat::default()).unwrap();
+        assert_eq!(entry.timestamp(), timestamp);
+        assert_eq!(entry.as_slice(), b"");
+    }
+
+    #[test]
+    fn empty_length_with_payload_is_too_much_input() {
+        for payload in [&b"x"[..], &[0x00, 0x01], &[0x00]] {
+            let mut frame = vec![0xFF; 4];
+            frame.extend(b"2024-01-02 03:04:05.678901");
+            frame.extend([0x00, 0x00]);
+            frame.extend(payload);
+            frame.extend([0x00; 4]);
+            let result = LogEntry::deserialize(&frame, &TimestampFormat::default());
+            assert!(
+                matches!(result, Err(DeserializeError::TooMuchInput)),
+                "{payload:?}"
+            );
+        }
+    }
+
+    /// reads at most `max_read` bytes at once
+    struct Limited<R> {
+        reader: R,
+        max_read: usize,
+    }
+
+    impl<R: Read> Read for Limited<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(self.max_read);
+            self.reader.read(&mut buf[..len])
+        }
+    }
+
+    #[test]
+    fn reader_returns_empty_entries() {
+        let payloads: [&[u8]; 5] = [b"first", b"", b"second", b"\0", b""];
+        let mut file = Vec::new();
+        for payload in payloads {
+            LogEntry::new(payload).serialize(&TimestampFormat::default(), &mut file);
+        }
+
+        // also delivered a byte at a time, so the zeros ending an empty entry arrive separately
+        for max_read in [usize::MAX, 1] {
+            let input = Limited {
+                reader: Cursor::new(&file),
+                max_read,
+            };
+            let mut entries = iter_entries(input);
+            for payload in payloads {
+                assert_eq!(entries.next().unwrap().unwrap().as_slice(), payload);
+            }
+            assert!(entries.next().is_none());
+            assert_eq!(entries.log_reader().garbage_bytes, 0);
+            assert_eq!(entries.log_reader().false_starts, 0);
+        }
+    }
+
+    #[test]
+    fn header_frame_layout() {
+        let mut frame = vec![0xFF; 4];
+        frame.extend(b"svmgr-log");
+        frame.push(1);
+        frame.extend(b"%Y%m%dT%H%M%S");
+        frame.extend([0x00; 4]);
+
+        let format = TimestampFormat::new("%Y%m%dT%H%M%S").unwrap();
+        let mut serialized = Vec::new();
+        format.serialize_header(&mut serialized);
+        assert_eq!(serialized, frame);
+        let deserialized = TimestampFormat::deserialize_header(&frame).unwrap();
+        assert_eq!(deserialized, Some(format));
+    }
+}
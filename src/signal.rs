@@ -0,0 +1,106 @@
+//! Signal handling
+//!
+//! Signals are blocked in the process and a dedicated thread waits for them with `sigwait`,
+//! forwarding them over a channel so they can be handled in an async context.
+//!
+//! This stands in for `tokio::signal`, whose `signal` feature pulls in `signal-hook-registry`. The
+//! receiving end of the channel is selected against other futures the same way. Unlike a handler,
+//! blocked signals never run their default action: a program which stops receiving from the
+//! channel without exiting can't be interrupted by `SIGINT` or `SIGTERM` anymore.
+//!
+//! Synchronous programs can use [`interrupt`] instead, which records the signal and interrupts the
+//! blocking system call in progress so the program notices it right away.
+
+use std::io;
+use std::mem::MaybeUninit;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use tokio::sync::mpsc;
+
+pub use libc::{SIGHUP, SIGINT, SIGTERM};
+
+/// Blocks `signals` and forwards them into the returned channel
+///
+/// The signal mask is inherited by threads spawned later, so this must be called before any other
+/// threads are spawned, otherwise they could still receive the signals and run the default action.
+pub fn forward(signals: &[libc::c_int]) -> io::Result<mpsc::UnboundedReceiver<libc::c_int>> {
+    let mut set = MaybeUninit::<libc::sigset_t>::uninit();
+    // SAFETY: `sigemptyset` initializes the set
+    let mut set = unsafe {
+        libc::sigemptyset(set.as_mut_ptr());
+        set.assume_init()
+    };
+    for &signal in signals {
+        // SAFETY: `set` is initialized
+        if unsafe { libc::sigaddset(&mut set, signal) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    // SAFETY: `set` is initialized and we don't care about the old mask
+    let ret = unsafe { libc::pthread_sigmask(libc::SIG_BLOCK, &set, ptr::null_mut()) };
+    if ret != 0 {
+        return Err(io::Error::from_raw_os_error(ret));
+    }
+
+    let (tx, rx) = mpsc::unbounded_channel();
+    thread::Builder::new()
+        .name("signals".to_owned())
+        .spawn(move || loop {
+            let mut signal = 0;
+            // SAFETY: `set` is initialized and the signals in it are blocked
+            if unsafe { libc::sigwait(&set, &mut signal) } != 0 {
+                // only fails for an invalid signal in the set which `sigaddset` already checked
+                continue;
+            }
+            if tx.send(signal).is_err() {
+                // nobody is listening anymore
+                break;
+            }
+        })?;
+
+    Ok(rx)
+}
+
+/// signals recorded by [`interrupt`], indexed by signal number
+static RECEIVED: [AtomicBool; 65] = [const { AtomicBool::new(false) }; 65];
+
+extern "C" fn record(signal: libc::c_int) {
+    // only async-signal-safe operations in here
+    if let Some(received) = RECEIVED.get(signal as usize) {
+        received.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Signal recorded by [`interrupt`]
+pub struct Received(libc::c_int);
+
+impl Received {
+    /// whether the signal arrived since the last call
+    pub fn take(&self) -> bool {
+        RECEIVED[self.0 as usize].swap(false, Ordering::Relaxed)
+    }
+}
+
+/// Records `signal` instead of running its default action
+///
+/// The handler is installed without `SA_RESTART`, so a blocking system call fails with `EINTR` when
+/// the signal arrives and the caller can check [`Received::take`] before retrying.
+pub fn interrupt(signal: libc::c_int) -> io::Result<Received> {
+    if RECEIVED.get(signal as usize).is_none() {
+        return Err(io::Error::from_raw_os_error(libc::EINVAL));
+    }
+    let mut action = MaybeUninit::<libc::sigaction>::zeroed();
+    // SAFETY: all-zero is a valid `sigaction`, `sigemptyset` initializes the mask
+    let mut action = unsafe {
+        libc::sigemptyset(&mut (*action.as_mut_ptr()).sa_mask);
+        action.assume_init()
+    };
+    action.sa_sigaction = record as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    // SAFETY: `action` is initialized and `record` is async-signal-safe
+    if unsafe { libc::sigaction(signal, &action, ptr::null_mut()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(Received(signal))
+}
@@ -0,0 +1,604 @@
+//! Supervision of units and its restart bookkeeping
+//!
+//! [`supervise`] runs a single service outside of the daemon: it starts the process, restarts it
+//! according to a [`Restart`] policy and publishes the [`UnitState`] through a
+//! [`SupervisorHandle`].
+//!
+//! Everything kept per unit has a fixed size: the start limit remembers the last
+//! [`MAX_START_BURST`] starts in a ring buffer and the backoff only counts consecutive failures.
+//! A unit which keeps crashing for months uses as much memory as one which never restarts, and
+//! [`Restarts::forget`] drops the state of units that were stopped.
+
+use crate::capture::LogCapture;
+use crate::config::{Run, StandardOutput, Type, Unit, UnitName};
+use camino::Utf8PathBuf as PathBuf;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::mem::MaybeUninit;
+use std::process::{Child, ExitStatus};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::sync::watch;
+
+/// most starts a [`StartLimit`] can allow within its interval
+pub const MAX_START_BURST: usize = 32;
+
+/// how often a running service's `logwrite` is checked, see [`LogCapture::check`]
+const LOGGER_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Allows at most `burst` starts of a unit within `interval`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StartLimit {
+    burst: usize,
+    interval: Duration,
+}
+
+impl StartLimit {
+    /// `burst` has to be between 1 and [`MAX_START_BURST`]
+    pub fn new(burst: usize, interval: Duration) -> Option<StartLimit> {
+        (1..=MAX_START_BURST)
+            .contains(&burst)
+            .then_some(StartLimit { burst, interval })
+    }
+}
+
+impl Default for StartLimit {
+    fn default() -> Self {
+        StartLimit {
+            burst: 5,
+            interval: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Delay before restarting a failed unit, doubling with every consecutive failure
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(60),
+        }
+    }
+}
+
+impl Backoff {
+    /// delay after `failures` consecutive failures, zero before the first one
+    pub fn delay(&self, failures: u32) -> Duration {
+        if failures == 0 {
+            return Duration::ZERO;
+        }
+        // past 2^31 any sane `max` is reached anyway
+        let factor = 1u32 << (failures - 1).min(31);
+        self.initial.saturating_mul(factor).min(self.max)
+    }
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("unit `{unit}` was started {burst} times within {interval:?}, not starting it again")]
+pub struct StartLimitHit {
+    unit: UnitName,
+    burst: usize,
+    interval: Duration,
+}
+
+/// Times of the most recent starts of a unit
+#[derive(Default)]
+struct StartHistory {
+    starts: [Option<Instant>; MAX_START_BURST],
+    /// slot the next start is recorded in, the oldest one
+    next: usize,
+}
+
+impl StartHistory {
+    /// records a start at `now` unless `limit` starts already happened within its interval
+    fn try_start(&mut self, limit: &StartLimit, now: Instant) -> bool {
+        let burst_ago = (self.next + MAX_START_BURST - limit.burst) % MAX_START_BURST;
+        if let Some(start) = self.starts[burst_ago] {
+            if now.saturating_duration_since(start) < limit.interval {
+                return false;
+            }
+        }
+        self.starts[self.next] = Some(now);
+        self.next = (self.next + 1) % MAX_START_BURST;
+        true
+    }
+}
+
+/// Restart state of one unit
+#[derive(Default)]
+struct UnitRestarts {
+    history: StartHistory,
+    /// failures since the last successful run
+    failures: u32,
+}
+
+/// Restart state of all running units
+#[derive(Default)]
+pub struct Restarts {
+    units: HashMap<UnitName, UnitRestarts>,
+}
+
+impl Restarts {
+    /// records a start of `unit` at `now` if `limit` allows it
+    pub fn start(
+        &mut self,
+        unit: &UnitName,
+        limit: &StartLimit,
+        now: Instant,
+    ) -> Result<(), StartLimitHit> {
+        let restarts = self.units.entry(unit.clone()).or_default();
+        if restarts.history.try_start(limit, now) {
+            Ok(())
+        } else {
+            Err(StartLimitHit {
+                unit: unit.clone(),
+                burst: limit.burst,
+                interval: limit.interval,
+            })
+        }
+    }
+
+    /// records how the process of `unit` exited, returns the delay before restarting it
+    pub fn exited(&mut self, unit: &UnitName, success: bool, backoff: &Backoff) -> Duration {
+        let restarts = self.units.entry(unit.clone()).or_default();
+        restarts.failures = if success {
+            0
+        } else {
+            restarts.failures.saturating_add(1)
+        };
+        backoff.delay(restarts.failures)
+    }
+
+    /// drops the state of a stopped unit, its next start begins with a clean slate
+    pub fn forget(&mut self, unit: &UnitName) {
+        self.units.remove(unit);
+    }
+
+    /// number of units with restart state
+    pub fn len(&self) -> usize {
+        self.units.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.units.is_empty()
+    }
+}
+
+/// When a service is started again after its process exited
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Restart {
+    #[default]
+    Always,
+    /// only after an unsuccessful exit
+    OnFailure,
+    Never,
+}
+
+impl Restart {
+    fn applies(self, success: bool) -> bool {
+        match self {
+            Restart::Always => true,
+            Restart::OnFailure => !success,
+            Restart::Never => false,
+        }
+    }
+}
+
+/// How [`supervise`] runs a service
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    pub restart: Restart,
+    pub start_limit: StartLimit,
+    pub backoff: Backoff,
+    /// `logwrite` binary for capturing the output, without it captured output is inherited
+    pub logwrite: Option<PathBuf>,
+    /// user whose log the captured output goes into, the system log by default
+    pub user: Option<UnitName>,
+}
+
+#[derive(Error, Debug)]
+pub enum SuperviseError {
+    #[error("unit `{0}` is not a service")]
+    NotAService(UnitName),
+    #[error("start `{unit}`")]
+    Spawn {
+        unit: UnitName,
+        #[source]
+        source: io::Error,
+    },
+    #[error("wait for `{unit}` to exit")]
+    Wait {
+        unit: UnitName,
+        #[source]
+        source: io::Error,
+    },
+    #[error("capture the output of `{unit}`")]
+    Capture {
+        unit: UnitName,
+        #[source]
+        source: io::Error,
+    },
+    #[error(transparent)]
+    StartLimit(#[from] StartLimitHit),
+}
+
+/// State of a supervised service
+#[derive(Clone, Debug)]
+pub enum UnitState {
+    /// the process is being started
+    Starting,
+    Running {
+        pid: u32,
+    },
+    /// the process exited and is started again after `delay`
+    Restarting {
+        status: ExitStatus,
+        delay: Duration,
+    },
+    /// the process exited and the restart policy doesn't start it again
+    Exited(ExitStatus),
+    /// stopped on request, with the exit status if the process was running
+    Stopped(Option<ExitStatus>),
+    /// supervision gave up
+    Failed(Arc<SuperviseError>),
+}
+
+impl UnitState {
+    /// whether supervision has ended, the state won't change anymore
+    pub fn is_final(&self) -> bool {
+        matches!(
+            self,
+            UnitState::Exited(_) | UnitState::Stopped(_) | UnitState::Failed(_)
+        )
+    }
+}
+
+/// Requests from the handle to the supervising thread
+#[derive(Default)]
+struct Control {
+    state: Mutex<ControlState>,
+    stop_requested: Condvar,
+}
+
+#[derive(Default)]
+struct ControlState {
+    stop: bool,
+    /// the running process, it isn't reaped while this is set so the pid can't be reused
+    pid: Option<libc::pid_t>,
+}
+
+impl Control {
+    fn lock(&self) -> MutexGuard<'_, ControlState> {
+        // the state stays consistent even if a holder panicked
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn stop(&self) {
+        let mut state = self.lock();
+        state.stop = true;
+        if let Some(pid) = state.pid {
+            terminate(pid);
+        }
+        self.stop_requested.notify_all();
+    }
+
+    /// waits for `delay` unless a stop is requested first, returns whether it was
+    fn sleep(&self, delay: Duration) -> bool {
+        let state = self.lock();
+        let (state, _) = self
+            .stop_requested
+            .wait_timeout_while(state, delay, |state| !state.stop)
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        state.stop
+    }
+}
+
+/// Handle of a service started with [`supervise`]
+///
+/// Dropping the handle doesn't stop the service, call [`SupervisorHandle::stop`] for that.
+pub struct SupervisorHandle {
+    state: watch::Receiver<UnitState>,
+    control: Arc<Control>,
+}
+
+impl SupervisorHandle {
+    /// the current state
+    pub fn state(&self) -> UnitState {
+        self.state.borrow().clone()
+    }
+
+    /// receiver of all state transitions
+    pub fn subscribe(&self) -> watch::Receiver<UnitState> {
+        self.state.clone()
+    }
+
+    /// waits until supervision has ended and returns the final state
+    pub async fn wait(&mut self) -> UnitState {
+        loop {
+            let state = self.state.borrow_and_update().clone();
+            // the thread always publishes a final state before it finishes
+            if state.is_final() || self.state.changed().await.is_err() {
+                return state;
+            }
+        }
+    }
+
+    /// asks the service to stop without waiting, the process gets `SIGTERM`
+    pub fn request_stop(&self) {
+        self.control.stop();
+    }
+
+    /// stops the service and waits until it has exited
+    pub async fn stop(&mut self) -> UnitState {
+        self.request_stop();
+        self.wait().await
+    }
+}
+
+/// Starts `unit` and keeps restarting it according to `options` on a dedicated thread
+///
+/// The start limit and backoff apply like in the daemon. While the service runs, its `logwrite` is
+/// checked every 100ms and replaced if it died. Supervision ends when the restart policy doesn't
+/// start the service again, when it's stopped through the handle or when the process can't be
+/// started.
+pub fn supervise(
+    name: UnitName,
+    unit: Unit,
+    options: Options,
+) -> Result<SupervisorHandle, SuperviseError> {
+    if !matches!(unit.unit_type(), Type::Service(_)) {
+        return Err(SuperviseError::NotAService(name));
+    }
+    let (state_tx, state_rx) = watch::channel(UnitState::Starting);
+    let control = Arc::new(Control::default());
+    let thread_control = Arc::clone(&control);
+    let thread_name = name.clone();
+    thread::Builder::new()
+        .name(format!("supervise {name}"))
+        .spawn(move || {
+            let state = run(&thread_name, &unit, &options, &state_tx, &thread_control)
+                .unwrap_or_else(|err| UnitState::Failed(Arc::new(err)));
+            state_tx.send_replace(state);
+        })
+        .map_err(|source| SuperviseError::Spawn {
+            unit: name.clone(),
+            source,
+        })?;
+    Ok(SupervisorHandle {
+        state: state_rx,
+        control,
+    })
+}
+
+/// the supervision loop, returns the final state
+fn run(
+    name: &UnitName,
+    unit: &Unit,
+    options: &Options,
+    state: &watch::Sender<UnitState>,
+    control: &Control,
+) -> Result<UnitState, SuperviseError> {
+    let capture_error = |source| SuperviseError::Capture {
+        unit: name.clone(),
+        source,
+    };
+    let mut capture = match &options.logwrite {
+        Some(logwrite) if unit.standard_output() == StandardOutput::Capture => {
+            Some(LogCapture::new(logwrite, options.user.as_ref(), name).map_err(capture_error)?)
+        }
+        _ => None,
+    };
+    let mut restarts = Restarts::default();
+
+    let mut run_unit = || -> Result<UnitState, SuperviseError> {
+        Ok(loop {
+            if control.lock().stop {
+                break UnitState::Stopped(None);
+            }
+            restarts.start(name, &options.start_limit, Instant::now())?;
+            state.send_replace(UnitState::Starting);
+            if let Some(capture) = &mut capture {
+                capture.check().map_err(capture_error)?;
+            }
+
+            let mut child =
+                spawn(unit, capture.as_ref()).map_err(|source| SuperviseError::Spawn {
+                    unit: name.clone(),
+                    source,
+                })?;
+            let pid = child.id();
+            {
+                let mut control = control.lock();
+                // the pid fits, it came from the kernel as a `pid_t`
+                control.pid = Some(pid as libc::pid_t);
+                if control.stop {
+                    terminate(pid as libc::pid_t);
+                }
+            }
+            state.send_replace(UnitState::Running { pid });
+
+            let wait_error = |source| SuperviseError::Wait {
+                unit: name.clone(),
+                source,
+            };
+            let status = loop {
+                // without a logger to look after there's no reason to wake up
+                let block = capture.is_none();
+                if let Some(status) = wait(control, &mut child, block).map_err(wait_error)? {
+                    break status;
+                }
+                if let Some(capture) = &mut capture {
+                    // a dead logger leaves the service blocked on a full pipe
+                    capture.check().map_err(capture_error)?;
+                }
+                thread::sleep(LOGGER_CHECK_INTERVAL);
+            };
+            if control.lock().stop {
+                break UnitState::Stopped(Some(status));
+            }
+            if !options.restart.applies(status.success()) {
+                break UnitState::Exited(status);
+            }
+            let delay = restarts.exited(name, status.success(), &options.backoff);
+            state.send_replace(UnitState::Restarting { status, delay });
+            if control.sleep(delay) {
+                break UnitState::Stopped(Some(status));
+            }
+        })
+    };
+    let final_state = run_unit();
+
+    // the logger is reaped on every way out, also when the supervision failed
+    let finished = capture.map(|capture| capture.finish().map_err(capture_error));
+    let final_state = final_state?;
+    finished.transpose()?;
+    Ok(final_state)
+}
+
+/// starts the process of `unit`, a shell script is written into the shell's stdin
+pub(crate) fn spawn(unit: &Unit, capture: Option<&LogCapture>) -> io::Result<Child> {
+    let mut command = unit.command()?;
+    if let Some(capture) = capture {
+        command.stdout(capture.stdio()?).stderr(capture.stdio()?);
+    }
+    let mut child = command.spawn()?;
+    if let (Run::Shell(script), Some(mut stdin)) = (unit.unit_type().run(), child.stdin.take()) {
+        // the shell may exit before reading all of it, that shows in its exit status
+        let _ = stdin.write_all(script.as_bytes());
+    }
+    Ok(child)
+}
+
+/// waits for `child` to exit and reaps it once it's no longer registered in `control`
+///
+/// without `block` it returns `None` right away if the child is still running.
+fn wait(control: &Control, child: &mut Child, block: bool) -> io::Result<Option<ExitStatus>> {
+    let mut info = MaybeUninit::<libc::siginfo_t>::zeroed();
+    let mut flags = libc::WEXITED | libc::WNOWAIT;
+    if !block {
+        flags |= libc::WNOHANG;
+    }
+    loop {
+        // SAFETY: `info` is valid for writes. `WNOWAIT` leaves the child waitable, so its pid
+        // stays reserved until `Child::wait` below
+        let ret = unsafe {
+            libc::waitid(
+                libc::P_PID,
+                child.id() as libc::id_t,
+                info.as_mut_ptr(),
+                flags,
+            )
+        };
+        if ret == 0 {
+            // SAFETY: `waitid` succeeded, with `WNOHANG` the pid is zero if nothing exited
+            if unsafe { info.assume_init_ref().si_pid() } == 0 {
+                return Ok(None);
+            }
+            break;
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+    control.lock().pid = None;
+    child.wait().map(Some)
+}
+
+/// sends `SIGTERM` to `pid`, a process which already exited is fine
+fn terminate(pid: libc::pid_t) {
+    // SAFETY: `kill` doesn't touch our memory, the caller guarantees the pid wasn't reaped yet
+    unsafe { libc::kill(pid, libc::SIGTERM) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flapping_unit_keeps_fixed_state() {
+        let unit = UnitName::new("flapping").unwrap();
+        let limit = StartLimit::new(MAX_START_BURST, Duration::from_millis(10)).unwrap();
+        let backoff = Backoff::default();
+        let mut restarts = Restarts::default();
+        let start = Instant::now();
+        for i in 0..10_000 {
+            // a start every millisecond stays below the limit of 32 within 10ms
+            let now = start + Duration::from_millis(i);
+            restarts.start(&unit, &limit, now).unwrap();
+            assert!(restarts.exited(&unit, false, &backoff) <= backoff.max);
+            assert_eq!(restarts.len(), 1);
+        }
+        let state = &restarts.units[&unit];
+        assert_eq!(state.failures, 10_000);
+        assert_eq!(state.history.starts.len(), MAX_START_BURST);
+
+        restarts.forget(&unit);
+        assert!(restarts.is_empty());
+    }
+
+    #[test]
+    fn start_limit_refuses_burst_within_interval() {
+        let interval = Duration::from_secs(10);
+        for burst in [1, 5, MAX_START_BURST] {
+            let limit = StartLimit::new(burst, interval).unwrap();
+            let mut history = StartHistory::default();
+            let start = Instant::now();
+            for i in 0..burst {
+                assert!(history.try_start(&limit, start + Duration::from_millis(i as u64)));
+            }
+            let last = start + Duration::from_millis(burst as u64 - 1);
+            // the oldest of the burst is still within the interval
+            assert!(!history.try_start(&limit, start + interval - Duration::from_nanos(1)));
+            // a refused start isn't recorded, once the oldest start is old enough it's allowed
+            assert!(history.try_start(&limit, start + interval));
+            assert!(!history.try_start(&limit, last + Duration::from_millis(1)));
+        }
+        assert_eq!(StartLimit::new(0, interval), None);
+        assert_eq!(StartLimit::new(MAX_START_BURST + 1, interval), None);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let backoff = Backoff {
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(1),
+        };
+        let delays: Vec<_> = (0..7).map(|failures| backoff.delay(failures)).collect();
+        let millis = |millis| Duration::from_millis(millis);
+        assert_eq!(
+            delays,
+            [
+                Duration::ZERO,
+                millis(100),
+                millis(200),
+                millis(400),
+                millis(800),
+                millis(1000),
+                millis(1000)
+            ]
+        );
+        for failures in [32, 33, 1000, u32::MAX] {
+            assert_eq!(backoff.delay(failures), backoff.max);
+        }
+
+        let mut restarts = Restarts::default();
+        let unit = UnitName::new("backoff").unwrap();
+        for _ in 0..3 {
+            restarts.exited(&unit, false, &backoff);
+        }
+        // a successful run resets the backoff
+        assert_eq!(restarts.exited(&unit, true, &backoff), Duration::ZERO);
+        assert_eq!(restarts.exited(&unit, false, &backoff), millis(100));
+    }
+}
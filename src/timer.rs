@@ -0,0 +1,65 @@
+//! Running the jobs of timer units
+//!
+//! The output of a job goes into the timer's log like a service's. Every run starts its own
+//! `logwrite` which logs a marker with the run number before the output, and once the job's output
+//! was drained another marker with its exit status follows. A [`TimerJob`] runs one job at a time,
+//! whoever schedules the runs has to skip a tick while a run is still going and must not run the
+//! same timer through a second `TimerJob`. As long as runs of one timer never overlap, the output
+//! between two markers always belongs to the run they name.
+
+use crate::capture::{self, LogCapture};
+use crate::config::{StandardOutput, Unit, UnitName};
+use crate::supervisor;
+use camino::Utf8PathBuf as PathBuf;
+use std::io;
+use std::process::ExitStatus;
+
+/// Runs the job of one timer unit and logs its output
+pub struct TimerJob {
+    logwrite: PathBuf,
+    user: Option<UnitName>,
+    name: UnitName,
+    /// runs started so far, numbers the runs in the markers
+    runs: u64,
+}
+
+impl TimerJob {
+    pub fn new(logwrite: impl Into<PathBuf>, user: Option<&UnitName>, name: &UnitName) -> Self {
+        TimerJob {
+            logwrite: logwrite.into(),
+            user: user.cloned(),
+            name: name.clone(),
+            runs: 0,
+        }
+    }
+
+    /// runs the job of `unit` once and waits until it and its output are done
+    ///
+    /// processes the job leaves behind with its stdout keep the run open until they exit as well.
+    pub fn run(&mut self, unit: &Unit) -> io::Result<ExitStatus> {
+        self.runs += 1;
+        let run = self.runs;
+        let capture = match unit.standard_output() {
+            StandardOutput::Capture => Some(LogCapture::with_marker(
+                &self.logwrite,
+                self.user.as_ref(),
+                &self.name,
+                Some(&format!("svmgr: run {run} started")),
+            )?),
+            StandardOutput::Inherit | StandardOutput::Null => None,
+        };
+        let status = supervisor::spawn(unit, capture.as_ref()).and_then(|mut job| job.wait());
+        let Some(capture) = capture else {
+            return status;
+        };
+
+        // `logwrite` has written all of the output once it exits, the end marker comes after it
+        capture.finish()?;
+        let marker = match &status {
+            Ok(status) => format!("svmgr: run {run} exited ({status})"),
+            Err(err) => format!("svmgr: run {run} failed to start: {err}"),
+        };
+        capture::log_marker(&self.logwrite, self.user.as_ref(), &self.name, &marker)?;
+        status
+    }
+}
@@ -0,0 +1,98 @@
+mod common;
+
+use camino::Utf8Path as Path;
+use std::fs;
+use std::mem::MaybeUninit;
+use std::os::unix::process::ExitStatusExt;
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+use svmgr::capture::LogCapture;
+use svmgr::log;
+
+/// runs `echo {text}` with its output captured, like a short-lived service
+fn echo(capture: &LogCapture, text: &str) {
+    let status = Command::new("sh")
+        .args(["-c", &format!("echo {text}")])
+        .stdout(capture.stdio().unwrap())
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+#[test]
+fn output_resumes_after_logwrite_is_killed() {
+    let tag = common::tag("capture-killed");
+    let mut capture = LogCapture::new(common::LOGWRITE, None, &tag).unwrap();
+    echo(&capture, "before");
+    common::wait_until("the first entry", || common::entries(&tag).len() == 1);
+
+    let pid = capture.logger_id() as libc::pid_t;
+    // SAFETY: `kill` doesn't touch our memory, the logger isn't reaped before `check`
+    assert_eq!(unsafe { libc::kill(pid, libc::SIGKILL) }, 0);
+    // a dying logger can still take what's in the pipe, wait until it's gone without reaping it
+    let mut info = MaybeUninit::<libc::siginfo_t>::zeroed();
+    // SAFETY: `info` is valid for writes, `WNOWAIT` leaves the logger for `check` to reap
+    let ret = unsafe {
+        libc::waitid(
+            libc::P_PID,
+            pid as libc::id_t,
+            info.as_mut_ptr(),
+            libc::WEXITED | libc::WNOWAIT,
+        )
+    };
+    assert_eq!(ret, 0);
+    // nobody reads the pipe now, the output waits in it
+    echo(&capture, "during");
+    let mut status = None;
+    common::wait_until("logwrite to be replaced", || {
+        status = capture.check().unwrap();
+        status.is_some()
+    });
+    assert_eq!(status.unwrap().signal(), Some(libc::SIGKILL));
+    echo(&capture, "after");
+    capture.finish().unwrap();
+
+    let entries = common::entries(&tag);
+    assert_eq!(entries[0], "before\n");
+    assert!(
+        entries[1].starts_with("svmgr: logger exited"),
+        "{:?}",
+        entries[1]
+    );
+    assert_eq!(entries[2..].concat(), "during\nafter\n");
+}
+
+#[test]
+fn rotation_is_seamless_for_followers() {
+    let tag = common::tag("capture-rotate");
+    let mut capture = LogCapture::new(common::LOGWRITE, None, &tag).unwrap();
+    let current = log::log_dir(None, &tag).join("current");
+    common::wait_until("the log file", || current.exists());
+    // without a saved position the whole file is read, whenever the follower gets to it
+    let cursors = Path::new(env!("CARGO_TARGET_TMPDIR")).join("capture-rotate.cursors");
+    let _ = fs::remove_file(&cursors);
+    let follower = common::Follower::start(&["--from-cursor", cursors.as_str(), tag.as_str()]);
+
+    let mut writer = Command::new("sh")
+        .args(["-c", "for i in $(seq 1 300); do echo $i; sleep 0.001; done"])
+        .stdout(capture.stdio().unwrap())
+        .spawn()
+        .unwrap();
+    for _ in 0..5 {
+        thread::sleep(Duration::from_millis(20));
+        capture.rotate().unwrap();
+        // `logwrite` creates the new file once it handled `SIGHUP`
+        common::wait_until("the new log file", || current.exists());
+    }
+    assert!(writer.wait().unwrap().success());
+    assert!(capture.check().unwrap().is_none());
+    capture.finish().unwrap();
+
+    let rotated = fs::read_dir(log::log_dir(None, &tag)).unwrap().count() - 1;
+    assert_eq!(rotated, 5);
+
+    let expected: Vec<_> = (1..=300).map(|i| i.to_string()).collect();
+    assert_eq!(follower.next(expected.len()), expected);
+    follower.assert_idle();
+}
@@ -0,0 +1,134 @@
+//! Helpers shared by the integration tests
+
+#![allow(dead_code)]
+
+use camino::{Utf8Path as Path, Utf8PathBuf as PathBuf};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+use std::sync::{mpsc, Once};
+use std::time::{Duration, Instant};
+use std::{env, fs, thread};
+use svmgr::config::{self, LoadedUnit, UnitName};
+use svmgr::log;
+
+pub const LOGWRITE: &str = env!("CARGO_BIN_EXE_logwrite");
+pub const LOGREAD: &str = env!("CARGO_BIN_EXE_logread");
+
+/// log directory of the tests, `SVMGR_LOG_DIR` points there for this process and its children
+pub fn log_base() -> PathBuf {
+    static INIT: Once = Once::new();
+    let base = Path::new(env!("CARGO_TARGET_TMPDIR")).join("logs");
+    INIT.call_once(|| {
+        fs::create_dir_all(&base).unwrap();
+        env::set_var("SVMGR_LOG_DIR", &base);
+    });
+    base
+}
+
+/// tag of a system log for a test, whatever a previous run left in its directory is removed
+pub fn tag(name: &str) -> UnitName {
+    let _ = fs::remove_dir_all(log_base().join(name));
+    UnitName::new(name).unwrap()
+}
+
+/// payloads of the entries in a log file, which has to be readable
+pub fn entries_in(file: &Path) -> Vec<String> {
+    log::iter_entries(File::open(file).unwrap())
+        .map(|entry| String::from_utf8(entry.unwrap().as_slice().to_vec()).unwrap())
+        .collect()
+}
+
+/// payloads of the entries in `current` of the system log `tag`, none if it doesn't exist yet
+pub fn entries(tag: &UnitName) -> Vec<String> {
+    let current = log::log_dir(None, tag).join("current");
+    match current.exists() {
+        true => entries_in(&current),
+        false => Vec::new(),
+    }
+}
+
+/// polls `condition` until it holds, fails the test after a few seconds
+pub fn wait_until(what: &str, mut condition: impl FnMut() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !condition() {
+        assert!(Instant::now() < deadline, "timed out waiting for {what}");
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// loads a unit file with `source` for a test, named `name`
+pub fn unit(name: &str, source: &str) -> LoadedUnit {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("units");
+    fs::create_dir_all(&dir).unwrap();
+    let file = dir.join(format!("{name}.toml"));
+    fs::write(&file, source).unwrap();
+    config::load_file(&file).unwrap()
+}
+
+/// `logread --follow` running in the background
+pub struct Follower {
+    child: Child,
+    lines: mpsc::Receiver<String>,
+}
+
+impl Follower {
+    /// starts it with `args`, the payloads of the printed lines are collected
+    pub fn start(args: &[&str]) -> Follower {
+        Follower::start_in(&log_base(), args)
+    }
+
+    /// like `start` with the logs in `base`
+    pub fn start_in(base: &Path, args: &[&str]) -> Follower {
+        let mut child = Command::new(LOGREAD)
+            .env("SVMGR_LOG_DIR", base)
+            .arg("--follow")
+            .args(args)
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        let (tx, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in stdout.lines() {
+                // the payload after the timestamp and tag
+                let line = line.unwrap();
+                let payload = line.splitn(4, ' ').nth(3).unwrap_or_default().to_owned();
+                if tx.send(payload).is_err() {
+                    break;
+                }
+            }
+        });
+        Follower { child, lines }
+    }
+
+    pub fn pid(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// the next `count` printed payloads
+    pub fn next(&self, count: usize) -> Vec<String> {
+        let mut lines = Vec::new();
+        while lines.len() < count {
+            match self.lines.recv_timeout(Duration::from_secs(10)) {
+                Ok(line) => lines.push(line),
+                Err(err) => panic!("{err} after {lines:?}"),
+            }
+        }
+        lines
+    }
+
+    /// fails if anything else is printed within a short while
+    pub fn assert_idle(&self) {
+        let next = self.lines.recv_timeout(Duration::from_millis(500));
+        assert!(next.is_err(), "unexpected {next:?}");
+    }
+}
+
+impl Drop for Follower {
+    // also when a test fails, it would keep the test's stderr open
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
@@ -0,0 +1,55 @@
+mod common;
+
+use camino::Utf8Path as Path;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::MetadataExt;
+use svmgr::log::{LogEntry, TimestampFormat};
+
+fn append(path: &Path, payloads: &[&str]) {
+    let mut bytes = Vec::new();
+    for payload in payloads {
+        LogEntry::new(payload.as_bytes()).serialize(&TimestampFormat::default(), &mut bytes);
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .unwrap();
+    file.write_all(&bytes).unwrap();
+}
+
+#[test]
+fn resumes_across_rotation() {
+    let tag = common::tag("cursor-rotation");
+    let dir = common::log_base().join(tag.as_str());
+    fs::create_dir_all(&dir).unwrap();
+    let current = dir.join("current");
+    // outside the log directory, where it would be taken for a rotated file
+    let cursors = Path::new(env!("CARGO_TARGET_TMPDIR")).join("cursor-rotation.cursors");
+    let _ = fs::remove_file(&cursors);
+    append(&current, &["a", "b"]);
+
+    let follower = common::Follower::start(&["--from-cursor", cursors.as_str(), tag.as_str()]);
+    assert_eq!(follower.next(2), ["a", "b"]);
+    let saved = format!(
+        "{tag} {} {}\n",
+        fs::metadata(&current).unwrap().ino(),
+        fs::metadata(&current).unwrap().len(),
+    );
+    common::wait_until("the position to be saved", || {
+        fs::read_to_string(&cursors).is_ok_and(|contents| contents == saved)
+    });
+    // killed, like an interrupted run
+    drop(follower);
+
+    // written while it wasn't running: the end of the old file and a new one
+    append(&current, &["c"]);
+    fs::rename(&current, dir.join("@1")).unwrap();
+    append(&current, &["d"]);
+
+    let follower = common::Follower::start(&["--from-cursor", cursors.as_str(), tag.as_str()]);
+    assert_eq!(follower.next(2), ["c", "d"]);
+    append(&current, &["e"]);
+    assert_eq!(follower.next(1), ["e"]);
+}
@@ -0,0 +1,76 @@
+mod common;
+
+use camino::Utf8Path as Path;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use svmgr::log::{LogEntry, TimestampFormat};
+
+fn append(path: &Path, payloads: &[&str]) {
+    let mut bytes = Vec::new();
+    for payload in payloads {
+        LogEntry::new(payload.as_bytes()).serialize(&TimestampFormat::default(), &mut bytes);
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .unwrap();
+    file.write_all(&bytes).unwrap();
+}
+
+/// the number of watches of every inotify instance `pid` has open
+fn inotify_watches(pid: u32) -> Vec<usize> {
+    let mut watches = Vec::new();
+    for fd in fs::read_dir(format!("/proc/{pid}/fd")).unwrap() {
+        let fd = fd.unwrap();
+        // closed since it was listed
+        let Ok(target) = fs::read_link(fd.path()) else {
+            continue;
+        };
+        if target.as_os_str() != "anon_inode:inotify" {
+            continue;
+        }
+        let fdinfo = format!("/proc/{pid}/fdinfo/{}", fd.file_name().to_string_lossy());
+        let fdinfo = fs::read_to_string(fdinfo).unwrap_or_default();
+        watches.push(
+            fdinfo
+                .lines()
+                .filter(|line| line.starts_with("inotify wd:"))
+                .count(),
+        );
+    }
+    watches
+}
+
+#[test]
+fn all_logs_share_one_inotify_instance() {
+    let base = Path::new(env!("CARGO_TARGET_TMPDIR")).join("discover-inotify");
+    let _ = fs::remove_dir_all(&base);
+    for log in ["a", "b"] {
+        fs::create_dir_all(base.join(log)).unwrap();
+        append(&base.join(log).join("current"), &[&format!("{log} old")]);
+    }
+
+    let follower = common::Follower::start_in(&base, &["--all"]);
+    // the base directory, and every log's directory and current file, which discovery and the
+    // log's follower both watch
+    common::wait_until("the logs to be watched", || {
+        inotify_watches(follower.pid()) == [5]
+    });
+    append(&base.join("a").join("current"), &["a new"]);
+    append(&base.join("b").join("current"), &["b new"]);
+    let mut payloads = follower.next(2);
+    payloads.sort();
+    assert_eq!(payloads, ["a new", "b new"]);
+
+    // a new log is read from the start, with the same instance
+    fs::create_dir_all(base.join("c")).unwrap();
+    append(&base.join("c").join("current"), &["c first"]);
+    assert_eq!(follower.next(1), ["c first"]);
+    common::wait_until("the new log to be watched", || {
+        inotify_watches(follower.pid()) == [7]
+    });
+    append(&base.join("c").join("current"), &["c second"]);
+    assert_eq!(follower.next(1), ["c second"]);
+    follower.assert_idle();
+}
@@ -0,0 +1,229 @@
+mod common;
+
+use camino::{Utf8Path as Path, Utf8PathBuf as PathBuf};
+use chrono::{NaiveDate, NaiveDateTime};
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+use std::thread;
+use std::time::Duration;
+use svmgr::config::UnitName;
+use svmgr::log::{self, LogEntry, TimestampFormat};
+
+/// runs logwrite with `args` and some input, logging into `base`
+fn logwrite(base: &Path, args: &[&str]) -> Output {
+    logwrite_input(base, args, b"entry\n")
+}
+
+fn logwrite_input(base: &Path, args: &[&str], input: &[u8]) -> Output {
+    let mut child = Command::new(common::LOGWRITE)
+        .args(args)
+        .env("SVMGR_LOG_DIR", base)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // it may have exited already, closing the pipe
+    let _ = child.stdin.take().unwrap().write_all(input);
+    child.wait_with_output().unwrap()
+}
+
+/// every path below `dir`
+fn tree(dir: &Path) -> Vec<String> {
+    let mut paths = Vec::new();
+    for entry in dir.read_dir().unwrap() {
+        let path = PathBuf::from_path_buf(entry.unwrap().path()).unwrap();
+        if path.is_dir() {
+            paths.extend(tree(&path));
+        }
+        paths.push(path.to_string());
+    }
+    paths.sort();
+    paths
+}
+
+#[test]
+fn path_like_names_are_rejected() {
+    let sandbox = Path::new(env!("CARGO_TARGET_TMPDIR")).join("logwrite-traversal");
+    let _ = fs::remove_dir_all(&sandbox);
+    let base = sandbox.join("logs").join("sv");
+    fs::create_dir_all(&base).unwrap();
+    let outside = sandbox.join("outside");
+
+    for name in ["..", ".", "a/b", "../outside", "/etc", outside.as_str(), ""] {
+        for args in [vec![name], vec!["--user", name, "tag"]] {
+            let output = logwrite(&base, &args);
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            assert!(!output.status.success(), "{args:?} was accepted");
+            assert!(stderr.contains("invalid unit name"), "{args:?}: {stderr}");
+        }
+    }
+    assert_eq!(
+        tree(&sandbox),
+        [base.parent().unwrap().as_str(), base.as_str()]
+    );
+    assert!(!Path::new("/etc/current").exists());
+    assert!(!Path::new("/etc/tag").exists());
+
+    // the same invocation with valid names logs inside the base directory
+    assert!(logwrite(&base, &["--user", "alice", "tag"])
+        .status
+        .success());
+    assert_eq!(
+        common::entries_in(&base.join("alice/tag/current")),
+        ["entry\n"]
+    );
+    fs::remove_dir_all(&sandbox).unwrap();
+}
+
+#[test]
+fn line_mode_splits_full_entries_at_newlines() {
+    let tag = common::tag("line-mode-split");
+    let mut input = b"short\n".to_vec();
+    input.resize(input.len() + 5000, b'x');
+    input.push(b'\n');
+    let output = logwrite_input(&common::log_base(), &["--line-mode", tag.as_str()], &input);
+    assert!(output.status.success());
+
+    let entries = common::entries(&tag);
+    assert_eq!(entries[0], "short\n");
+    // a line longer than an entry is cut, the rest of it is the next entry
+    assert_eq!(entries[1], "x".repeat(4096));
+    assert_eq!(entries[2], "x".repeat(5000 - 4096) + "\n");
+    assert_eq!(entries.len(), 3);
+}
+
+#[test]
+fn line_timeout_waits_for_input_to_stop() {
+    let tag = common::tag("line-mode-trickle");
+    let mut child = Command::new(common::LOGWRITE)
+        .args(["--line-mode", "--line-timeout", "1s", tag.as_str()])
+        .stdin(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+
+    // takes longer than the timeout, but never pauses that long
+    for _ in 0..15 {
+        stdin.write_all(b"x").unwrap();
+        thread::sleep(Duration::from_millis(100));
+    }
+    stdin.write_all(b"\n").unwrap();
+    common::wait_until("the line to be logged", || {
+        !common::entries(&tag).is_empty()
+    });
+    assert_eq!(common::entries(&tag), ["x".repeat(15) + "\n"]);
+
+    // once the input stops the partial line is logged while logwrite keeps running
+    stdin.write_all(b"partial").unwrap();
+    common::wait_until("the partial line to be logged", || {
+        common::entries(&tag).len() == 2
+    });
+    assert_eq!(common::entries(&tag)[1], "partial");
+
+    drop(stdin);
+    assert!(child.wait().unwrap().success());
+}
+
+fn frames(payloads: &[&str]) -> Vec<u8> {
+    let mut frames = Vec::new();
+    for payload in payloads {
+        LogEntry::new(payload.as_bytes()).serialize(&TimestampFormat::default(), &mut frames);
+    }
+    frames
+}
+
+#[test]
+fn trailing_garbage_in_raw_input_is_corrupt() {
+    let tag = common::tag("raw-in-trailing");
+    let mut input = frames(&["a"]);
+    input.extend(b"junk");
+
+    let output = logwrite_input(&common::log_base(), &["--raw-in", tag.as_str()], &input);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(
+        stderr.contains("4 bytes of unrecognized input, use `--skip-corrupt`"),
+        "{stderr}"
+    );
+
+    let tag = common::tag("raw-in-trailing-skipped");
+    let args = ["--raw-in", "--skip-corrupt", tag.as_str()];
+    let output = logwrite_input(&common::log_base(), &args, &input);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+    assert!(stderr.contains("skipping 4 bytes of unrecognized input"));
+    assert_eq!(common::entries(&tag), ["a"]);
+}
+
+/// timestamps and payloads of the entries in `current` of the system log `tag`
+fn timestamped(tag: &UnitName) -> Vec<(NaiveDateTime, Vec<u8>)> {
+    let current = log::log_dir(None, tag).join("current");
+    log::iter_entries(fs::File::open(current).unwrap())
+        .map(|entry| {
+            let entry = entry.unwrap();
+            (entry.timestamp(), entry.as_slice().to_vec())
+        })
+        .collect()
+}
+
+#[test]
+fn raw_output_round_trips_through_raw_input() {
+    let source = common::tag("raw-round-trip-source");
+    let args = [
+        "--timestamp-format",
+        "%d.%m.%Y %H:%M:%S%.3f",
+        source.as_str(),
+    ];
+    let base = common::log_base();
+    assert!(logwrite_input(&base, &args, b"first\n").status.success());
+    assert!(logwrite_input(&base, &[source.as_str()], b"second\n\0\xff")
+        .status
+        .success());
+
+    let raw = Command::new(common::LOGREAD)
+        .args(["--raw", source.as_str()])
+        .output()
+        .unwrap();
+    assert!(raw.status.success());
+    let copy = common::tag("raw-round-trip-copy");
+    let output = logwrite_input(&base, &["--raw-in", copy.as_str()], &raw.stdout);
+    assert!(output.status.success());
+
+    let entries = timestamped(&source);
+    assert_eq!(entries.len(), 2);
+    assert_eq!(timestamped(&copy), entries);
+}
+
+#[test]
+fn monotonic_continues_after_the_last_entry() {
+    let tag = common::tag("monotonic-seed");
+    let dir = log::log_dir(None, &tag);
+    fs::create_dir_all(&dir).unwrap();
+    // written with a clock far ahead, or before it was set back
+    let ahead = NaiveDate::from_ymd(2100, 1, 1).and_hms(0, 0, 0);
+    let mut file = Vec::new();
+    LogEntry::new(b"ahead")
+        .with_timestamp(ahead)
+        .serialize(&TimestampFormat::default(), &mut file);
+    fs::write(dir.join("current"), file).unwrap();
+
+    let base = common::log_base();
+    let output = logwrite_input(&base, &["--monotonic", tag.as_str()], b"now\n");
+    assert!(output.status.success());
+    let entries = timestamped(&tag);
+    let payloads: Vec<_> = entries
+        .iter()
+        .map(|(_, payload)| String::from_utf8_lossy(payload))
+        .collect();
+    assert_eq!(payloads.len(), 3);
+    assert!(payloads[1].starts_with("svmgr: timestamps went back by"));
+    assert_eq!(payloads[2], "now\n");
+    assert!(entries.iter().all(|(timestamp, _)| *timestamp == ahead));
+
+    // without the option the clock is used as is
+    let output = logwrite_input(&base, &[tag.as_str()], b"unclamped\n");
+    assert!(output.status.success());
+    assert!(timestamped(&tag)[3].0 < ahead);
+}
@@ -0,0 +1,53 @@
+mod common;
+
+use camino::Utf8Path as Path;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use svmgr::log::{LogEntry, TimestampFormat};
+
+fn append(path: &Path, payloads: &[&str]) {
+    let mut bytes = Vec::new();
+    for payload in payloads {
+        LogEntry::new(payload.as_bytes()).serialize(&TimestampFormat::default(), &mut bytes);
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .unwrap();
+    file.write_all(&bytes).unwrap();
+}
+
+#[test]
+fn polling_follows_appends_and_rotation() {
+    let tag = common::tag("poll-rotation");
+    let dir = common::log_base().join(tag.as_str());
+    fs::create_dir_all(&dir).unwrap();
+    let current = dir.join("current");
+    // without saved positions it starts at the beginning, so we know when it's running
+    let cursors = Path::new(env!("CARGO_TARGET_TMPDIR")).join("poll-rotation.cursors");
+    let _ = fs::remove_file(&cursors);
+    append(&current, &["a"]);
+
+    let follower = common::Follower::start(&[
+        "--poll",
+        "--poll-interval",
+        "50ms",
+        "--from-cursor",
+        cursors.as_str(),
+        tag.as_str(),
+    ]);
+    assert_eq!(follower.next(1), ["a"]);
+
+    append(&current, &["b", "c"]);
+    assert_eq!(follower.next(2), ["b", "c"]);
+
+    // the end of the old file comes before the new one
+    append(&current, &["d"]);
+    fs::rename(&current, dir.join("@1")).unwrap();
+    append(&current, &["e"]);
+    assert_eq!(follower.next(2), ["d", "e"]);
+    append(&current, &["f"]);
+    assert_eq!(follower.next(1), ["f"]);
+    follower.assert_idle();
+}
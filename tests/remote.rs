@@ -0,0 +1,136 @@
+mod common;
+
+use camino::Utf8Path as Path;
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::process::{Child, Command};
+use svmgr::log::{LogEntry, TimestampFormat};
+
+fn append(path: &Path, payloads: &[&str]) {
+    let mut bytes = Vec::new();
+    for payload in payloads {
+        LogEntry::new(payload.as_bytes()).serialize(&TimestampFormat::default(), &mut bytes);
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .unwrap();
+    file.write_all(&bytes).unwrap();
+}
+
+/// a localhost address nothing listens on
+fn free_addr() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+/// `logread --serve` running in the background
+struct Server {
+    child: Child,
+}
+
+impl Server {
+    fn start(addr: &str) -> Server {
+        common::log_base();
+        let child = Command::new(common::LOGREAD)
+            .args(["--serve", addr])
+            .spawn()
+            .unwrap();
+        common::wait_until("the server to listen", || TcpStream::connect(addr).is_ok());
+        Server { child }
+    }
+
+    /// how many clients the server is tailing `file` for
+    fn tailing(&self, file: &Path) -> usize {
+        let fds = fs::read_dir(format!("/proc/{}/fd", self.child.id())).unwrap();
+        fds.filter(|fd| {
+            let fd = fd.as_ref().unwrap();
+            fs::read_link(fd.path()).is_ok_and(|target| target == file)
+        })
+        .count()
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// sends `request` and returns everything the server answers before closing the connection
+fn request(addr: &str, request: &[u8]) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(request).unwrap();
+    // a request without a newline is complete once nothing more can follow
+    stream.shutdown(Shutdown::Write).unwrap();
+    let mut response = String::new();
+    // the server may close before reading all of an overlong request
+    let _ = stream.read_to_string(&mut response);
+    response
+}
+
+#[test]
+fn entries_arrive_from_the_server() {
+    let tag = common::tag("remote-follow");
+    let dir = common::log_base().join(tag.as_str());
+    fs::create_dir_all(&dir).unwrap();
+    let current = dir.join("current");
+    // only new entries are served
+    append(&current, &["old"]);
+
+    let addr = free_addr();
+    let server = Server::start(&addr);
+    let first = common::Follower::start(&["--remote", &addr, tag.as_str()]);
+    common::wait_until("the server to tail the log", || {
+        server.tailing(&current) == 1
+    });
+    append(&current, &["a", "b"]);
+    assert_eq!(first.next(2), ["a", "b"]);
+
+    // a second client of the same log
+    let second = common::Follower::start(&["--remote", &addr, tag.as_str()]);
+    common::wait_until("the server to tail the log twice", || {
+        server.tailing(&current) == 2
+    });
+    append(&current, &["c"]);
+    assert_eq!(first.next(1), ["c"]);
+    assert_eq!(second.next(1), ["c"]);
+
+    // the clients reconnect to a restarted server
+    drop(server);
+    let server = Server::start(&addr);
+    common::wait_until("the clients to reconnect", || server.tailing(&current) == 2);
+    append(&current, &["d"]);
+    assert_eq!(first.next(1), ["d"]);
+    assert_eq!(second.next(1), ["d"]);
+    first.assert_idle();
+}
+
+#[test]
+fn malformed_requests_are_refused() {
+    let tag = common::tag("remote-refused");
+    fs::create_dir_all(common::log_base().join(tag.as_str())).unwrap();
+    let addr = free_addr();
+    let _server = Server::start(&addr);
+
+    let response = request(&addr, b"../etc\n");
+    assert!(
+        response.starts_with("error: invalid unit name"),
+        "{response}"
+    );
+    let response = request(&addr, b"remote-missing\n");
+    assert_eq!(response, "error: log `remote-missing` does not exist\n");
+    // closed without an answer
+    assert_eq!(request(&addr, "x".repeat(2000).as_bytes()), "");
+    assert_eq!(request(&addr, b"no newline"), "");
+
+    // the server still answers valid requests
+    let mut stream = TcpStream::connect(&addr).unwrap();
+    stream.write_all(format!("{tag}\n").as_bytes()).unwrap();
+    let mut ok = [0; 3];
+    stream.read_exact(&mut ok).unwrap();
+    assert_eq!(&ok, b"ok\n");
+}
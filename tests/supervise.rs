@@ -0,0 +1,233 @@
+mod common;
+
+use camino::Utf8Path as Path;
+use std::fs;
+use std::time::Duration;
+use svmgr::log;
+use svmgr::supervisor::{self, Backoff, Options, Restart, StartLimit, SuperviseError, UnitState};
+
+#[tokio::test]
+async fn uncaptured_output_starts_no_logger() {
+    for output in ["null", "inherit"] {
+        let name = format!("no-capture-{output}");
+        common::tag(&name);
+        let source = format!(
+            r#"
+            [Service]
+            Exec = ["true"]
+            standard_output = "{output}"
+            "#
+        );
+        // a logger would fail to start from here and fail the supervision
+        for logwrite in ["/nonexistent/logwrite", common::LOGWRITE] {
+            let loaded = common::unit(&name, &source);
+            let options = Options {
+                restart: Restart::Never,
+                logwrite: Some(logwrite.into()),
+                ..Options::default()
+            };
+            let mut handle =
+                supervisor::supervise(loaded.name.clone(), loaded.unit, options).unwrap();
+            match handle.wait().await {
+                UnitState::Exited(status) => assert!(status.success(), "{name}"),
+                state => panic!("{name} with {logwrite}: {state:?}"),
+            }
+            assert!(!log::log_dir(None, &loaded.name).exists(), "{name}");
+        }
+    }
+
+    // the same unit with its output captured does get a log
+    let tag = common::tag("no-capture-control");
+    let loaded = common::unit(
+        tag.as_str(),
+        r#"
+        [Service]
+        Exec = ["echo", "captured"]
+        "#,
+    );
+    let options = Options {
+        restart: Restart::Never,
+        logwrite: Some(common::LOGWRITE.into()),
+        ..Options::default()
+    };
+    let mut handle = supervisor::supervise(loaded.name, loaded.unit, options).unwrap();
+    assert!(matches!(handle.wait().await, UnitState::Exited(_)));
+    assert_eq!(common::entries(&tag), ["captured\n"]);
+}
+
+/// pid of the `logwrite` writing the system log `tag`
+fn logwrite_pid(tag: &str) -> Option<libc::pid_t> {
+    for entry in fs::read_dir("/proc").unwrap() {
+        let entry = entry.unwrap();
+        let Ok(pid) = entry.file_name().to_string_lossy().parse() else {
+            continue;
+        };
+        let Ok(cmdline) = fs::read(entry.path().join("cmdline")) else {
+            continue;
+        };
+        let mut args = cmdline.split(|&byte| byte == 0);
+        if args.next() == Some(common::LOGWRITE.as_bytes()) && args.any(|arg| arg == tag.as_bytes())
+        {
+            return Some(pid);
+        }
+    }
+    None
+}
+
+#[tokio::test]
+async fn logger_is_replaced_while_the_service_runs() {
+    let tag = common::tag("supervise-logger-killed");
+    let flag = Path::new(env!("CARGO_TARGET_TMPDIR")).join("supervise-logger-killed.flag");
+    let _ = fs::remove_file(&flag);
+    let loaded = common::unit(
+        tag.as_str(),
+        &format!(
+            r#"
+            [Service]
+            Shell = """
+            echo before
+            while [ ! -e '{flag}' ]; do sleep 0.01; done
+            echo after
+            """
+            "#
+        ),
+    );
+    let options = Options {
+        restart: Restart::Never,
+        logwrite: Some(common::LOGWRITE.into()),
+        ..Options::default()
+    };
+    let mut handle = supervisor::supervise(loaded.name, loaded.unit, options).unwrap();
+    common::wait_until("the first entry", || common::entries(&tag).len() == 1);
+
+    let pid = logwrite_pid(tag.as_str()).unwrap();
+    // SAFETY: `kill` doesn't touch our memory, the supervisor only reaps it in `check`
+    assert_eq!(unsafe { libc::kill(pid, libc::SIGKILL) }, 0);
+    // the service is still running, only the supervisor's periodic check can notice
+    common::wait_until("the logger to be replaced", || {
+        common::entries(&tag).len() == 2
+    });
+    assert!(matches!(handle.state(), UnitState::Running { .. }));
+
+    fs::write(&flag, "").unwrap();
+    assert!(matches!(handle.wait().await, UnitState::Exited(status) if status.success()));
+    let entries = common::entries(&tag);
+    assert_eq!(entries[0], "before\n");
+    assert!(
+        entries[1].starts_with("svmgr: logger exited"),
+        "{entries:?}"
+    );
+    assert_eq!(entries[2..].concat(), "after\n");
+}
+
+#[tokio::test]
+async fn short_lived_service_is_restarted() {
+    let tag = common::tag("supervise-restarts");
+    let counter = Path::new(env!("CARGO_TARGET_TMPDIR")).join("supervise-restarts.count");
+    let _ = fs::remove_file(&counter);
+    // fails twice, then succeeds
+    let loaded = common::unit(
+        tag.as_str(),
+        &format!(
+            r#"
+            [Service]
+            Shell = """
+            n=$(($(cat '{counter}' 2>/dev/null || echo 0) + 1))
+            echo $n > '{counter}'
+            echo run $n
+            [ $n -ge 3 ]
+            """
+            "#
+        ),
+    );
+    let options = Options {
+        restart: Restart::OnFailure,
+        backoff: Backoff {
+            initial: Duration::from_millis(100),
+            max: Duration::from_millis(100),
+        },
+        logwrite: Some(common::LOGWRITE.into()),
+        ..Options::default()
+    };
+    let mut handle = supervisor::supervise(loaded.name, loaded.unit, options).unwrap();
+    let mut states = handle.subscribe();
+    let mut restarts = 0;
+    let final_state = loop {
+        let state = states.borrow_and_update().clone();
+        if let UnitState::Restarting { status, delay } = &state {
+            assert!(!status.success());
+            assert_eq!(*delay, Duration::from_millis(100));
+            restarts += 1;
+        }
+        if state.is_final() {
+            break state;
+        }
+        states.changed().await.unwrap();
+    };
+    assert!(matches!(final_state, UnitState::Exited(status) if status.success()));
+    // the backoff delay is long enough for every restart to be seen
+    assert_eq!(restarts, 2);
+    assert!(matches!(handle.wait().await, UnitState::Exited(_)));
+    assert_eq!(common::entries(&tag).concat(), "run 1\nrun 2\nrun 3\n");
+}
+
+#[tokio::test]
+async fn start_limit_ends_restart_loop() {
+    let tag = common::tag("supervise-start-limit");
+    let loaded = common::unit(
+        tag.as_str(),
+        r#"
+        [Service]
+        Exec = ["sh", "-c", "echo run; exit 1"]
+        "#,
+    );
+    let options = Options {
+        start_limit: StartLimit::new(3, Duration::from_secs(60)).unwrap(),
+        backoff: Backoff {
+            initial: Duration::from_millis(1),
+            max: Duration::from_millis(1),
+        },
+        logwrite: Some(common::LOGWRITE.into()),
+        ..Options::default()
+    };
+    let mut handle = supervisor::supervise(loaded.name, loaded.unit, options).unwrap();
+    match handle.wait().await {
+        UnitState::Failed(err) => assert!(matches!(*err, SuperviseError::StartLimit(_))),
+        state => panic!("{state:?}"),
+    }
+    assert_eq!(common::entries(&tag).concat(), "run\n".repeat(3));
+}
+
+#[tokio::test]
+async fn logger_is_reaped_when_the_start_limit_is_hit() {
+    let tag = common::tag("supervise-start-limit-reaped");
+    let loaded = common::unit(
+        tag.as_str(),
+        r#"
+        [Service]
+        Exec = ["sh", "-c", "sleep 0.2; exit 1"]
+        "#,
+    );
+    let options = Options {
+        start_limit: StartLimit::new(3, Duration::from_secs(60)).unwrap(),
+        backoff: Backoff {
+            initial: Duration::from_millis(1),
+            max: Duration::from_millis(1),
+        },
+        logwrite: Some(common::LOGWRITE.into()),
+        ..Options::default()
+    };
+    let mut handle = supervisor::supervise(loaded.name, loaded.unit, options).unwrap();
+    let mut logger = None;
+    common::wait_until("the logger to start", || {
+        logger = logwrite_pid(tag.as_str());
+        logger.is_some()
+    });
+    match handle.wait().await {
+        UnitState::Failed(err) => assert!(matches!(*err, SuperviseError::StartLimit(_))),
+        state => panic!("{state:?}"),
+    }
+    // a zombie would still have its entry
+    let logger = logger.unwrap();
+    assert!(!Path::new(&format!("/proc/{logger}")).exists());
+}
@@ -0,0 +1,53 @@
+mod common;
+
+use svmgr::timer::TimerJob;
+
+#[test]
+fn runs_are_delimited_by_markers() {
+    let tag = common::tag("timer-runs");
+    let loaded = common::unit(
+        tag.as_str(),
+        r#"
+        [Timer]
+        on_startup = true
+        Shell = """
+        echo "output of $RUN"
+        echo "more output of $RUN" >&2
+        test "$RUN" != 2
+        """
+        "#,
+    );
+    let mut job = TimerJob::new(common::LOGWRITE, None, &loaded.name);
+    for run in 1..=3 {
+        std::env::set_var("RUN", run.to_string());
+        let status = job.run(&loaded.unit).unwrap();
+        assert_eq!(status.success(), run != 2, "run {run}");
+    }
+
+    // the output of every run is in whole entries between its two markers
+    let entries = common::entries(&tag);
+    let mut entries = entries.iter().map(String::as_str);
+    for run in 1..=3 {
+        assert_eq!(
+            entries.next(),
+            Some(format!("svmgr: run {run} started").as_str())
+        );
+        let mut output = String::new();
+        let exited = format!("svmgr: run {run} exited");
+        let end = loop {
+            match entries.next() {
+                Some(entry) if entry.starts_with(&exited) => break entry,
+                Some(entry) => output.push_str(entry),
+                None => panic!("run {run} has no exit marker"),
+            }
+        };
+        assert_eq!(output, format!("output of {run}\nmore output of {run}\n"));
+        let status = if run == 2 {
+            "exit status: 1"
+        } else {
+            "exit status: 0"
+        };
+        assert_eq!(end, format!("{exited} ({status})"));
+    }
+    assert_eq!(entries.next(), None);
+}